@@ -0,0 +1,76 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use vecmat::*;
+
+use world::*;
+use particle::*;
+
+
+// The blast from one nitro cell
+pub const nitro_energy: f64 = 6.0;
+pub const nitro_radius: i32 = 6;
+// How fast loose cells right next to a blast are thrown, in cells per tick
+const blast_speed: f64 = 3.0;
+
+/// Blows up everything around `center`.
+///
+/// The blast is modelled as rays cast outwards in every direction. Each ray starts with
+/// `energy`, loses `energy/radius` per cell travelled, and loses the hardness of every cell it
+/// breaks. A cell that's at least as hard as the ray's remaining energy stops the ray.
+/// Broken cells are destroyed, except that nitro is set off, flammable cells are set alight,
+/// and loose powders and fluids are thrown outwards as particles.
+pub fn explode<R: Rng>(grid: &mut Grid, center: Vec2<i32>, energy: f64, radius: i32, rng: &mut R) {
+  let diameter = (radius*2+1) as usize;
+  let mut visited = vec![false; diameter*diameter];
+
+  // Enough rays to hit every cell on the edge of the blast
+  let num_rays = 8*radius;
+  for i in 0..num_rays {
+    let angle = i as f64 / num_rays as f64 * 2.0 * PI;
+    let dir = Vec2(angle.cos(), angle.sin());
+    let mut ray_energy = energy;
+    for step in 1..radius+1 {
+      ray_energy -= energy / radius as f64;
+      if ray_energy <= 0.0 {
+        break;
+      }
+      let offset = Vec2((dir.x*step as f64).round() as i32, (dir.y*step as f64).round() as i32);
+      let pos = center + offset;
+      if !grid.in_range(pos) {
+        break;
+      }
+      let typ = grid[pos].typ;
+      let hardness = typ.hardness(grid);
+      if hardness >= ray_energy {
+        break;
+      }
+      ray_energy -= hardness;
+
+      let index = (offset.y+radius) as usize*diameter + (offset.x+radius) as usize;
+      if visited[index] {
+        continue;
+      }
+      visited[index] = true;
+
+      let strength = ray_energy / energy;
+      match typ {
+        CellType::Granular(GranularType::Nitro, _, _, _) => grid[pos].typ = CellType::ExplodingNitro,
+        // Already set off, maybe by another blast this tick. It'll go off by itself.
+        CellType::ExplodingNitro => (),
+        CellType::Empty => if strength > 0.5 && rng.gen::<f64>() < 0.3 {
+          grid[pos].typ = CellType::Fire;
+        },
+        _ => if let Some(fuel) = typ.fuel(grid) {
+          grid[pos].typ = fuel.ignite(grid);
+        } else if is_loose(typ) {
+          // Thrown cells leave the grid straight away, so later rays pass through where they were
+          launch(grid, pos, dir * (strength * blast_speed));
+        } else {
+          grid[pos].typ = CellType::Empty;
+        },
+      }
+    }
+  }
+}
@@ -0,0 +1,131 @@
+use std::cmp;
+
+use vecmat::*;
+
+use world::*;
+
+
+// How much light fades with each cell it travels, on top of what the cells it passes through absorb
+const light_falloff: f64 = 0.08;
+// Nothing is drawn darker than this, so you can still see what you're doing at night
+const min_light: f64 = 0.1;
+// How many times light is swept across the grid each tick. Each pair of sweeps lets light turn one more corner.
+const light_sweeps: i32 = 2;
+
+/// Global lighting settings, and how much light reaches each cell.
+/// Sunlight shines down from the top of the world, against gravity (from the top of the screen
+/// when there's none), and fire, torches, lava and lit lamps
+/// light up their surroundings. Light passes through empty space and see-through materials like
+/// water, ice and glass, and is blocked by everything else.
+pub struct Lighting {
+  pub enabled: bool,
+  // 0.0-1.0. 1.0 is midday and 0.0 is night.
+  pub daylight: f64,
+  // The light reaching each cell, from 0.0-1.0
+  levels: Vec<Vec<f64>>,
+}
+
+impl Lighting {
+  pub fn new(size: Vec2<i32>) -> Lighting {
+    Lighting{enabled: false, daylight: 1.0, levels: vec![vec![0.0; size.x as usize]; size.y as usize]}
+  }
+
+  /// How brightly lit a cell is, from 0.0-1.0. Everything is fully lit while lighting is off.
+  pub fn level(&self, pos: Vec2<i32>) -> f64 {
+    if !self.enabled {
+      return 1.0;
+    }
+    match self.levels.get(pos.y as usize).and_then(|row| row.get(pos.x as usize)) {
+      Some(&level) => level.max(min_light),
+      None => min_light,
+    }
+  }
+}
+
+/// Works out how much light reaches each cell
+pub fn update_light(grid: &mut Grid) {
+  let (width, height) = (grid.size.x as usize, grid.size.y as usize);
+
+  let mut transparency = vec![vec![0.0; width]; height];
+  let mut sources = vec![vec![0.0; width]; height];
+  for y in 0..height {
+    for x in 0..width {
+      let pos = Vec2(x as i32, y as i32);
+      let typ = grid[pos].typ;
+      transparency[y][x] = typ.transparency(grid);
+      sources[y][x] = typ.light_source(grid, pos).unwrap_or(0.0);
+    }
+  }
+  let mut levels = sources.clone();
+
+  // Sunlight falls from outside the world until something blocks it. The cell that blocks it is
+  // still lit. Each cell's sunlight comes from the cell above it, so follow each column upwards
+  // until it reaches the sky or a cell that's already been worked out. Past a void or solid edge
+  // is open sky, and a column that wraps around the world without reaching either is too.
+  let mut sunlight = vec![vec![None; width]; height];
+  for y in 0..height {
+    for x in 0..width {
+      if sunlight[y][x].is_some() {
+        continue;
+      }
+      let mut column = vec![Vec2(x as i32, y as i32)];
+      let mut light = grid.lighting.daylight;
+      // The limit stops this from looping forever when the column wraps around the world
+      for _ in 0..cmp::max(grid.size.x, grid.size.y) {
+        let pos = column[column.len()-1];
+        let above = match grid.resolve(pos - grid.down_at(pos).unwrap_or(Vec2(0, 1))) {
+          Some(above) => above,
+          None => break,
+        };
+        let (ax, ay) = (above.x as usize, above.y as usize);
+        match sunlight[ay][ax] {
+          Some(level) => {
+            light = level * transparency[ay][ax];
+            break;
+          },
+          None => column.push(above),
+        }
+      }
+      for pos in column.iter().rev() {
+        let (px, py) = (pos.x as usize, pos.y as usize);
+        sunlight[py][px] = Some(light);
+        levels[py][px] = levels[py][px].max(light);
+        light *= transparency[py][px];
+      }
+    }
+  }
+
+  // Spread the light out, first from the top left and then from the bottom right.
+  // Light sources shine on their neighbours even when they're opaque.
+  let shine = |levels: &Vec<Vec<f64>>, (x, y): (usize, usize)| {
+    (levels[y][x] * transparency[y][x]).max(sources[y][x]) - light_falloff
+  };
+  for _ in 0..light_sweeps {
+    for y in 0..height {
+      for x in 0..width {
+        let mut level = levels[y][x];
+        if x > 0 {
+          level = level.max(shine(&levels, (x-1, y)));
+        }
+        if y > 0 {
+          level = level.max(shine(&levels, (x, y-1)));
+        }
+        levels[y][x] = level;
+      }
+    }
+    for y in (0..height).rev() {
+      for x in (0..width).rev() {
+        let mut level = levels[y][x];
+        if x+1 < width {
+          level = level.max(shine(&levels, (x+1, y)));
+        }
+        if y+1 < height {
+          level = level.max(shine(&levels, (x, y+1)));
+        }
+        levels[y][x] = level;
+      }
+    }
+  }
+
+  grid.lighting.levels = levels;
+}
@@ -24,7 +24,9 @@ use num::*;
 // use std::iter::*;
 use rand::Rng;
 use std::cmp;
-use std::path::Path;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use timer::*;
 use vecmat::*;
@@ -38,47 +40,110 @@ use gui::event::*;
 use gui::text::*;
 
 mod world;
+mod pattern;
+mod explosion;
+mod particle;
+mod weather;
+mod light;
 
 use world::*;
+use pattern::*;
+use particle::*;
 
 // #[cfg(windows)] #[link_args = "-Wl,--subsystem,windows"] extern {}
 
 const fps: i32 = 60;
 const dt: f64 = 1.0 / fps as f64;
+// Releasing the mouse while moving at least this many cells per frame throws the cells under the brush
+const flick_threshold: i32 = 3;
+// The velocity thrown cells get, as a fraction of the mouse's
+const flick_strength: f64 = 0.5;
 
 
 fn main() {
   let cell_types = vec![CellType::Empty,
     CellType::Solid(SolidType::Wall),
     CellType::Solid(SolidType::Ice),
-    CellType::Granular(GranularType::Sand, false, false),
-    CellType::Granular(GranularType::Dirt, false, false),
-    CellType::Granular(GranularType::Snow, false, false),
-    CellType::Granular(GranularType::Nitro, false, false),
+    CellType::Solid(SolidType::Metal),
+    CellType::Solid(SolidType::Obsidian),
+    CellType::Solid(SolidType::Glass),
+    CellType::Granular(GranularType::Sand, false, false, 0.0),
+    CellType::Granular(GranularType::Dirt, false, false, 0.0),
+    CellType::Granular(GranularType::Snow, false, false, 0.0),
+    CellType::Granular(GranularType::Nitro, false, false, 0.0),
+    CellType::Granular(GranularType::Stone, false, false, 0.0),
     CellType::Fluid(FluidType::Water, 1.0),
     CellType::Fluid(FluidType::Oil, 1.0),
     CellType::Fluid(FluidType::Methane, 1.0),
     CellType::Fluid(FluidType::Steam, 1.0),
     CellType::Fluid(FluidType::Cement, 1.0),
-    CellType::WaterGenerator,
-    CellType::SandGenerator,
-    CellType::Destroyer,
+    CellType::Fluid(FluidType::Lava, 1.0),
+    CellType::Fluid(FluidType::Acid, 1.0),
+    CellType::Fluid(FluidType::Mud, 1.0),
+    CellType::Fluid(FluidType::Cloud, 1.0),
+    CellType::WaterGenerator(false),
+    CellType::SandGenerator(false),
+    CellType::Destroyer(false),
     CellType::Plant,
+    CellType::Granular(GranularType::GrassSeed, false, false, 0.0),
+    CellType::Granular(GranularType::ShrubSeed, false, false, 0.0),
+    CellType::Granular(GranularType::TreeSeed, false, false, 0.0),
     CellType::Fire,
-    CellType::Torch,
-    CellType::LifeOn,
+    CellType::Torch(false),
+    CellType::LifeOn(LifeType::Classic),
+    CellType::LifeOn(LifeType::Conway),
+    CellType::LifeOn(LifeType::HighLife),
+    CellType::LifeOn(LifeType::DayNight),
+    CellType::LifeOn(LifeType::Seeds),
     CellType::Wire(WireType::Normal),
     CellType::Wire(WireType::Input),
     CellType::Wire(WireType::Output),
     CellType::Wire(WireType::Nor(false)),
     CellType::Wire(WireType::SignalEmitter),
+    CellType::Wire(WireType::And),
+    CellType::Wire(WireType::Or),
+    CellType::Wire(WireType::Xor),
+    CellType::Wire(WireType::Diode),
+    CellType::Wire(WireType::Clock(12, 0)),
+    CellType::Wire(WireType::Delay(5, 0)),
+    CellType::WaterGenerator(true),
+    CellType::SandGenerator(true),
+    CellType::Destroyer(true),
+    CellType::Torch(true),
+    CellType::Door(0),
+    CellType::Valve(0),
+    CellType::Lamp(0),
+    // Detects whatever was selected before it; see select_cell_type
+    CellType::Sensor(SensorType::Material(Material::Granular(GranularType::Sand)), false),
+    CellType::Sensor(SensorType::FluidLevel(FluidType::Water, 0.5), false),
+    CellType::Sensor(SensorType::Fire, false),
+    CellType::Sensor(SensorType::Life, false),
     CellType::ElectronHead(WireType::Normal),
     CellType::Eater,
     CellType::Fuse(false),
     CellType::Virus(virus_lifetime),
   ];
 
+  let boundary_presets = vec![
+    ("solid", Boundaries::all(Boundary::Solid)),
+    ("void", Boundaries::all(Boundary::Void)),
+    ("wrap", Boundaries::all(Boundary::Wrap)),
+    // The inflow is filled in with the selected cell type when the preset is chosen
+    ("inflow", Boundaries{top: Boundary::Inflow(CellType::Empty, 0.0),
+      bottom: Boundary::Void, left: Boundary::Solid, right: Boundary::Solid}),
+  ];
+
   let world_size = Vec2(1200/cell_size, 750/cell_size);
+
+  let gravity_presets = vec![
+    ("down", Gravity::Uniform(Vec2(0, 1))),
+    ("left", Gravity::Uniform(Vec2(-1, 0))),
+    ("up", Gravity::Uniform(Vec2(0, -1))),
+    ("right", Gravity::Uniform(Vec2(1, 0))),
+    ("diagonal", Gravity::Uniform(Vec2(1, 1))),
+    ("planet", Gravity::Point(world_size/2)),
+    ("zero-g", Gravity::Zero),
+  ];
   // println!("{}", world_size);
 
   let mut rng = rand::thread_rng();
@@ -89,11 +154,37 @@ fn main() {
   let mut window = Window::new(window_mode/*, &resource_path*/);
   let font = Font::new(&(resource_path.join("DejaVuSans.ttf")), 14, &window);
 
+  // Patterns are loaded from resources/patterns and from any paths given on the command line
+  let mut pattern_paths = Vec::new();
+  if let Ok(entries) = fs::read_dir(resource_path.join("patterns")) {
+    for entry in entries {
+      if let Ok(entry) = entry {
+        pattern_paths.push(entry.path());
+      }
+    }
+  }
+  pattern_paths.sort();
+  pattern_paths.extend(env::args().skip(1).map(PathBuf::from));
+  let mut patterns = Vec::new();
+  for path in pattern_paths.iter() {
+    match Pattern::load(path) {
+      Ok(pattern) => patterns.push(pattern),
+      Err(err) => println!("{}", err),
+    }
+  }
+
   // let mut fps_logger = FPSLogger::new(1.0);
 
   let mut quit_button = Button::new(font.clone(), "Quit");
   let mut pause_button = Button::new(font.clone(), "Pause");
   let mut step_button = Button::new(font.clone(), "Step");
+  let mut overlay_button = Button::new(font.clone(), "View: normal");
+  let mut boundary_button = Button::new(font.clone(), "Edges: solid");
+  let mut stamp_button = Button::new(font.clone(), "Stamp: none");
+  let mut circuit_button = Button::new(font.clone(), "Simulate: all");
+  let mut weather_button = Button::new(font.clone(), "Weather: off");
+  let mut gravity_button = Button::new(font.clone(), "Gravity: down");
+  let mut light_button = Button::new(font.clone(), "Lighting: off");
 
   let mut shape_buttons = vec![
     (Button::new(font.clone(), "Circle"), Brush::Circle),
@@ -131,11 +222,23 @@ fn main() {
 
   let mut cur_cell_type_index = 3;
   let mut cur_cell_type = cell_types[cur_cell_type_index];
+  // What material sensors placed from now on detect
+  let mut sensor_target = cur_cell_type;
 
   let mut brush = Brush::Circle;
   let mut brush_size = 10;
 
   let mut old_mouse_pos = None;
+  // How far the mouse was dragged (in cells) this frame, for flicking cells
+  let mut mouse_velocity: Vec2<i32> = Vec2::zero();
+
+  let mut boundary_preset_index = 0;
+  // The chance per tick of each empty cell along an inflow edge being filled
+  let mut inflow_rate = 0.002;
+  let mut gravity_preset_index = 0;
+
+  // When a pattern is selected, clicking stamps it instead of drawing with the brush
+  let mut stamp_index: Option<usize> = None;
 
   while !window.should_close() {
     // fps_logger.update();
@@ -143,7 +246,11 @@ fn main() {
 
 
     if !paused {
-      world.simulate(&mut rng);
+      if let Some(pos) = world.simulate(&mut rng) {
+        println!("Breakpoint hit at ({}, {})", pos.x, pos.y);
+        paused = true;
+        pause_button.set_text("Unpause");
+      }
     }
     world.update_mesh(&window);
 
@@ -153,6 +260,13 @@ fn main() {
         (LWidget(&mut gap4), 1.0),
         (LWidget(&mut pause_button), 0.0),
         (LWidget(&mut step_button), 0.0),
+        (LWidget(&mut overlay_button), 0.0),
+        (LWidget(&mut boundary_button), 0.0),
+        (LWidget(&mut stamp_button), 0.0),
+        (LWidget(&mut circuit_button), 0.0),
+        (LWidget(&mut weather_button), 0.0),
+        (LWidget(&mut gravity_button), 0.0),
+        (LWidget(&mut light_button), 0.0),
         (LWidget(&mut gap0), 1.0),
       ];
       for widget in cell_type_widgets.iter_mut() {
@@ -179,7 +293,7 @@ fn main() {
     for (i, widget) in cell_type_widgets.iter_mut().enumerate() {
       if widget.was_pressed() {
         cur_cell_type_index = i;
-        cur_cell_type = cell_types[cur_cell_type_index];
+        cur_cell_type = select_cell_type(cell_types[cur_cell_type_index], &mut sensor_target, &world.grid);
       }
       if cur_cell_type_index == i {
         widget.set_text_color(Color4::red());
@@ -203,7 +317,48 @@ fn main() {
     if step_button.was_pressed() {
       paused = true;
       pause_button.set_text("Unpause");
-      world.simulate(&mut rng);
+      if let Some(pos) = world.simulate(&mut rng) {
+        println!("Breakpoint hit at ({}, {})", pos.x, pos.y);
+      }
+    }
+    if overlay_button.was_pressed() {
+      world.overlay = world.overlay.next();
+      overlay_button.set_text(&format!("View: {}", world.overlay.name()));
+    }
+    if boundary_button.was_pressed() {
+      boundary_preset_index = (boundary_preset_index+1) % boundary_presets.len();
+      let (name, boundaries) = boundary_presets[boundary_preset_index];
+      world.grid.boundaries = boundaries.with_inflow(cur_cell_type, inflow_rate);
+      boundary_button.set_text(&format!("Edges: {}", name));
+    }
+    if circuit_button.was_pressed() {
+      world.electrical_only = !world.electrical_only;
+      circuit_button.set_text(if world.electrical_only {"Simulate: circuits"} else {"Simulate: all"});
+    }
+    if gravity_button.was_pressed() {
+      gravity_preset_index = (gravity_preset_index+1) % gravity_presets.len();
+      let (name, gravity) = gravity_presets[gravity_preset_index];
+      world.grid.gravity = gravity;
+      gravity_button.set_text(&format!("Gravity: {}", name));
+    }
+    if weather_button.was_pressed() {
+      world.grid.weather.enabled = !world.grid.weather.enabled;
+      weather_button.set_text(if world.grid.weather.enabled {"Weather: on"} else {"Weather: off"});
+    }
+    if light_button.was_pressed() {
+      world.grid.lighting.enabled = !world.grid.lighting.enabled;
+      light_button.set_text(if world.grid.lighting.enabled {"Lighting: on"} else {"Lighting: off"});
+    }
+    if stamp_button.was_pressed() {
+      stamp_index = match stamp_index {
+        None if !patterns.is_empty() => Some(0),
+        Some(i) if i+1 < patterns.len() => Some(i+1),
+        _ => None,
+      };
+      match stamp_index {
+        Some(i) => stamp_button.set_text(&format!("Stamp: {}", patterns[i].name)),
+        None => stamp_button.set_text("Stamp: none"),
+      }
     }
 
     for &mut (ref mut button, shape) in &mut shape_buttons {
@@ -244,11 +399,71 @@ fn main() {
             glfw::Key::Space => {
               paused = true;
               pause_button.set_text("Unpause");
-              world.simulate(&mut rng);
+              if let Some(pos) = world.simulate(&mut rng) {
+                println!("Breakpoint hit at ({}, {})", pos.x, pos.y);
+              }
+            },
+            glfw::Key::E => {
+              world.electrical_only = !world.electrical_only;
+              circuit_button.set_text(if world.electrical_only {"Simulate: circuits"} else {"Simulate: all"});
+            },
+            glfw::Key::LeftBracket => {
+              world.trace_ticks = cmp::max(world.trace_ticks-5, 5);
+              println!("Tracing signals for {} ticks", world.trace_ticks);
+            },
+            glfw::Key::RightBracket => {
+              world.trace_ticks += 5;
+              println!("Tracing signals for {} ticks", world.trace_ticks);
+            },
+            glfw::Key::Up => {
+              world.grid.gravity_strength += 0.25;
+              println!("Gravity strength: {}", world.grid.gravity_strength);
+            },
+            glfw::Key::Down => {
+              world.grid.gravity_strength = (world.grid.gravity_strength - 0.25).max(0.0);
+              println!("Gravity strength: {}", world.grid.gravity_strength);
+            },
+            glfw::Key::Minus => {
+              world.grid.weather.temperature -= 5.0;
+              println!("Temperature: {}", world.grid.weather.temperature);
+            },
+            glfw::Key::Equal => {
+              world.grid.weather.temperature += 5.0;
+              println!("Temperature: {}", world.grid.weather.temperature);
+            },
+            glfw::Key::Comma => {
+              world.grid.weather.humidity = (world.grid.weather.humidity - 0.1).max(0.0);
+              println!("Humidity: {:.1}", world.grid.weather.humidity);
+            },
+            glfw::Key::Period => {
+              world.grid.weather.humidity = (world.grid.weather.humidity + 0.1).min(1.0);
+              println!("Humidity: {:.1}", world.grid.weather.humidity);
+            },
+            glfw::Key::Semicolon => {
+              world.grid.lighting.daylight = (world.grid.lighting.daylight - 0.1).max(0.0);
+              println!("Daylight: {:.1}", world.grid.lighting.daylight);
+            },
+            glfw::Key::Apostrophe => {
+              world.grid.lighting.daylight = (world.grid.lighting.daylight + 0.1).min(1.0);
+              println!("Daylight: {:.1}", world.grid.lighting.daylight);
+            },
+            glfw::Key::K => {
+              inflow_rate = (inflow_rate / 2.0).max(0.0005);
+              world.grid.boundaries = world.grid.boundaries.with_inflow(cur_cell_type, inflow_rate);
+              println!("Inflow rate: {}", inflow_rate);
+            },
+            glfw::Key::L => {
+              inflow_rate = (inflow_rate * 2.0).min(1.0);
+              world.grid.boundaries = world.grid.boundaries.with_inflow(cur_cell_type, inflow_rate);
+              println!("Inflow rate: {}", inflow_rate);
+            },
+            glfw::Key::O => {
+              world.overlay = world.overlay.next();
+              overlay_button.set_text(&format!("View: {}", world.overlay.name()));
             },
             glfw::Key::Num2 => {
               cur_cell_type_index = (cur_cell_type_index+1) % cell_types.len();
-              cur_cell_type = cell_types[cur_cell_type_index];
+              cur_cell_type = select_cell_type(cell_types[cur_cell_type_index], &mut sensor_target, &world.grid);
             },
             glfw::Key::Num1 => {
               if cur_cell_type_index == 0 {
@@ -256,7 +471,7 @@ fn main() {
               } else {
                 cur_cell_type_index -= 1;
               }
-              cur_cell_type = cell_types[cur_cell_type_index];
+              cur_cell_type = select_cell_type(cell_types[cur_cell_type_index], &mut sensor_target, &world.grid);
             },
             glfw::Key::W => {
               let mut total_water = 0.0;
@@ -279,9 +494,16 @@ fn main() {
         _ => ()
       }
     }
+    // Releasing the mouse usually comes a frame after the last move, so remember that move too
+    let last_mouse_velocity = mouse_velocity;
+    mouse_velocity = Vec2::zero();
     for event in window.get_widget_events(&world).into_iter() {
       match event {
         // TODO: make this work when holding the mouse button down
+        Event::MouseButton(glfw::MouseButton::Button1, Action::Press, _, pos) if stamp_index.is_some() => {
+          let pos = Vec2(pos.x as i32, pos.y as i32);
+          patterns[stamp_index.unwrap()].stamp(&mut world.grid, pos/cell_size);
+        },
         Event::MouseButton(glfw::MouseButton::Button1, Action::Press, _, pos) => {
           let pos = Vec2(pos.x as i32, pos.y as i32);
           let old_mouse_pos2 = match old_mouse_pos {
@@ -291,16 +513,42 @@ fn main() {
           brush.draw(brush_size, pos/cell_size, old_mouse_pos2, cur_cell_type, &mut world, &mut rng);
           old_mouse_pos = Some(pos/cell_size);
         },
+        // Right click traces where a signal from that cell can go
+        Event::MouseButton(glfw::MouseButton::Button2, Action::Press, _, pos) => {
+          // Clicks past a wrapping edge trace from the cell they wrap to
+          if let Some(pos) = world.grid.resolve(Vec2(pos.x as i32, pos.y as i32)/cell_size) {
+            world.trace_origin = Some(pos);
+            world.overlay = Overlay::SignalReach;
+            overlay_button.set_text(&format!("View: {}", world.overlay.name()));
+          }
+        },
+        // Middle click toggles a breakpoint on an output wire
+        Event::MouseButton(glfw::MouseButton::Button3, Action::Press, _, pos) => {
+          if let Some(pos) = world.grid.resolve(Vec2(pos.x as i32, pos.y as i32)/cell_size) {
+            if !world.toggle_breakpoint(pos) {
+              println!("Breakpoints can only be set on output wires");
+            }
+          }
+        },
         Event::MouseButton(glfw::MouseButton::Button1, Action::Release, _, pos) => {
+          let pos = Vec2(pos.x as i32, pos.y as i32)/cell_size;
+          let flick = if mouse_velocity == Vec2::zero() {last_mouse_velocity} else {mouse_velocity};
+          if stamp_index.is_none() && cmp::max(flick.x.abs(), flick.y.abs()) >= flick_threshold {
+            let vel = Vec2(flick.x as f64, flick.y as f64) * flick_strength;
+            for point in brush.get_points(brush_size, pos, &mut rng).into_iter() {
+              launch(&mut world.grid, point, vel);
+            }
+          }
           old_mouse_pos = None;
         },
-        Event::MouseMove(pos, ref buttons) if buttons.contains(&glfw::MouseButton::Button1) => {
+        Event::MouseMove(pos, ref buttons) if buttons.contains(&glfw::MouseButton::Button1) && stamp_index.is_none() => {
           let pos = Vec2(pos.x as i32, pos.y as i32);
           let old_mouse_pos2 = match old_mouse_pos {
             None => pos/cell_size,
             Some(pos) => pos
           };
           brush.draw(brush_size, pos/cell_size, old_mouse_pos2, cur_cell_type, &mut world, &mut rng);
+          mouse_velocity = pos/cell_size - old_mouse_pos2;
           old_mouse_pos = Some(pos/cell_size);
         },
         _ => ()
@@ -318,6 +566,22 @@ fn main() {
 }
 
 
+/// The cell type to draw with when `typ` is picked from the palette. Material sensors detect the
+/// material of the cell type picked before them, so pick that and then the sensor.
+fn select_cell_type(typ: CellType, sensor_target: &mut CellType, grid: &Grid) -> CellType {
+  match typ {
+    CellType::Sensor(SensorType::Material(_), powered) => {
+      println!("Material sensors detect: {}", sensor_target.name(grid));
+      CellType::Sensor(SensorType::Material(sensor_target.material()), powered)
+    },
+    _ => {
+      *sensor_target = typ;
+      typ
+    },
+  }
+}
+
+
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum Brush {
   Circle,
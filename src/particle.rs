@@ -0,0 +1,138 @@
+use std::cmp;
+
+use vecmat::*;
+
+use world::*;
+
+
+// Acceleration due to gravity at full strength, in cells per tick per tick
+pub const particle_gravity: f64 = 0.15;
+// Particles never move more than this many cells per tick, so they can't tunnel far into the scenery
+pub const max_particle_speed: f64 = 4.0;
+// How far a particle that can't land on top of what it hit looks for somewhere else to land
+const landing_radius: i32 = 4;
+
+/// A cell that's been knocked out of the grid and is flying freely.
+/// It moves ballistically until it hits something, then lands back in the grid as a normal cell.
+#[derive(Copy, Clone)]
+pub struct Particle {
+  pub typ: CellType,
+  pub pos: Vec2<f64>,
+  pub vel: Vec2<f64>,
+}
+
+impl Particle {
+  /// The grid cell the particle is over
+  pub fn cell(&self) -> Vec2<i32> {
+    Vec2(self.pos.x.round() as i32, self.pos.y.round() as i32)
+  }
+}
+
+/// Whether a cell can be thrown as a particle
+pub fn is_loose(typ: CellType) -> bool {
+  match typ {
+    CellType::Granular(..) | CellType::Fluid(..) => true,
+    _ => false,
+  }
+}
+
+/// Takes a loose cell out of the grid and throws it with the given velocity.
+/// Returns false (and leaves the cell alone) if it isn't loose.
+pub fn launch(grid: &mut Grid, pos: Vec2<i32>, vel: Vec2<f64>) -> bool {
+  if !grid.in_range(pos) || !is_loose(grid[pos].typ) {
+    return false;
+  }
+  let typ = grid[pos].typ;
+  grid[pos].typ = CellType::Empty;
+  grid.particles.push(Particle{typ: typ, pos: Vec2(pos.x as f64, pos.y as f64), vel: vel});
+  true
+}
+
+/// What happens to a particle at the end of a tick
+enum Fate {Flying, Landed, Lost}
+
+/// Moves every particle by one tick, landing the ones that hit something
+pub fn move_particles(grid: &mut Grid) {
+  let particles = ::std::mem::replace(&mut grid.particles, Vec::new());
+  for mut particle in particles.into_iter() {
+    particle.vel = particle.vel + grid.pull_at(particle.pos) * particle_gravity;
+    let speed = particle.vel.norm();
+    if speed > max_particle_speed {
+      particle.vel = particle.vel * (max_particle_speed / speed);
+    }
+
+    // Move at most one cell at a time so we notice everything in the way
+    let steps = particle.vel.x.abs().max(particle.vel.y.abs()).ceil().max(1.0);
+    let step = particle.vel / steps;
+    let mut fate = Fate::Flying;
+    for _ in 0..steps as i32 {
+      let next = Particle{pos: particle.pos + step, ..particle};
+      let cell = next.cell();
+      if cell == particle.cell() {
+        particle = next;
+        continue;
+      }
+      match grid.resolve(cell) {
+        Some(resolved) if grid[resolved].typ == CellType::Empty => {
+          // Keep the particle's position inside the grid across wrapping edges
+          particle = Particle{pos: next.pos + Vec2((resolved.x - cell.x) as f64, (resolved.y - cell.y) as f64), ..next};
+        },
+        // Flew off a void edge
+        None if grid.in_range(cell) => {
+          fate = Fate::Lost;
+          break;
+        },
+        // Hit something, or a solid edge
+        _ => {
+          fate = Fate::Landed;
+          break;
+        },
+      }
+    }
+
+    match fate {
+      Fate::Flying => grid.particles.push(particle),
+      Fate::Landed => land(grid, particle),
+      Fate::Lost => (),
+    }
+  }
+}
+
+/// Puts a particle back into the grid where it is, or on top of whatever moved into its way.
+/// If the pile reaches the edge of the world, it lands in the nearest empty cell instead, and if
+/// there's nowhere nearby it stays in flight and tries again next tick.
+fn land(grid: &mut Grid, particle: Particle) {
+  match landing_spot(grid, particle.cell()) {
+    Some(pos) => {
+      grid[pos].typ = particle.typ;
+      grid.update(pos);
+    },
+    None => grid.particles.push(Particle{vel: Vec2::zero(), ..particle}),
+  }
+}
+
+fn landing_spot(grid: &Grid, start: Vec2<i32>) -> Option<Vec2<i32>> {
+  let empty_cell = |pos| grid.resolve(pos).into_iter().find(|&pos| grid[pos].typ == CellType::Empty);
+  let mut pos = start;
+  for _ in 0..cmp::max(grid.size.x, grid.size.y) {
+    match grid.resolve(pos) {
+      Some(resolved) if grid[resolved].typ == CellType::Empty => return Some(resolved),
+      // "On top" follows gravity. Without any, just look upwards on the screen.
+      Some(resolved) => pos = resolved - grid.down_at(resolved).unwrap_or(Vec2(0, 1)),
+      None => break,
+    }
+  }
+  // Look in squares of growing size around where it hit
+  for r in 1..landing_radius+1 {
+    for y in -r..r+1 {
+      for x in -r..r+1 {
+        if cmp::max(x.abs(), y.abs()) == r {
+          if let Some(pos) = empty_cell(start + Vec2(x, y)) {
+            return Some(pos);
+          }
+        }
+      }
+    }
+  }
+  None
+}
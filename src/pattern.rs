@@ -0,0 +1,168 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use vecmat::*;
+
+use world::*;
+
+
+/// What the states in a pattern mean
+#[derive(Clone, PartialEq)]
+pub enum PatternKind {
+  /// State 1 is a live cell. The rule is used to pick a matching Life species.
+  Life(Option<LifeRule>),
+  /// State 1 is an electron head, 2 is an electron tail and 3 is a wire.
+  /// Note that our wire rules are close to, but not exactly the same as, Wireworld's.
+  Wireworld,
+}
+
+/// A pattern loaded from a Golly RLE file or a Wireworld .wi file, which can be stamped onto the grid
+#[derive(Clone)]
+pub struct Pattern {
+  pub name: String,
+  pub size: Vec2<i32>,
+  pub kind: PatternKind,
+  // Non-empty cells only
+  cells: Vec<(Vec2<i32>, u8)>,
+}
+
+impl Pattern {
+  pub fn load(path: &Path) -> Result<Pattern, String> {
+    let mut file = try!(File::open(path).map_err(|e| format!("Can't open {}: {}", path.display(), e)));
+    let mut text = String::new();
+    try!(file.read_to_string(&mut text).map_err(|e| format!("Can't read {}: {}", path.display(), e)));
+    let name = path.file_stem().map(|x| x.to_string_lossy().into_owned()).unwrap_or(String::new());
+    match path.extension().and_then(|x| x.to_str()) {
+      Some("rle") => Pattern::parse_rle(name, &text),
+      Some("wi") => Pattern::parse_wi(name, &text),
+      _ => Err(format!("Unknown pattern format: {}", path.display())),
+    }
+  }
+
+  /// Parses a Golly RLE pattern. Both two-state Life patterns (`b`/`o`) and multi-state
+  /// patterns (`.`/`A`/`B`/...) are supported; the latter are treated as Wireworld if the rule says so.
+  pub fn parse_rle(name: String, text: &str) -> Result<Pattern, String> {
+    let mut name = name;
+    let mut kind = PatternKind::Life(None);
+    let mut cells = Vec::new();
+    let mut header_found = false;
+    let mut pos = Vec2(0, 0);
+    let mut size = Vec2(0, 0);
+    let mut count = String::new();
+
+    'lines: for line in text.lines() {
+      let line = line.trim();
+      if line.starts_with("#N") {
+        name = line[2..].trim().to_string();
+        continue;
+      }
+      if line.starts_with("#") || line.is_empty() {
+        continue;
+      }
+      if !header_found {
+        header_found = true;
+        for part in line.split(',') {
+          let mut kv = part.splitn(2, '=');
+          let key = kv.next().unwrap_or("").trim();
+          let value = kv.next().unwrap_or("").trim();
+          if key == "rule" {
+            kind = if value.to_lowercase().starts_with("wireworld") {
+              PatternKind::Wireworld
+            } else {
+              // Golly allows suffixes like ":T100,100" for bounded grids
+              PatternKind::Life(LifeRule::parse(value.split(':').next().unwrap_or(value)).ok())
+            };
+          }
+        }
+        continue;
+      }
+
+      for c in line.chars() {
+        if c.is_digit(10) {
+          count.push(c);
+          continue;
+        }
+        let run = if count.is_empty() {1} else {try!(count.parse::<i32>().map_err(|e| format!("Invalid run count in RLE: {}", e)))};
+        count.clear();
+        let state = match c {
+          'b' | '.' => 0,
+          'o' => 1,
+          'A'...'X' => c as u8 - b'A' + 1,
+          '$' => {
+            pos = Vec2(0, pos.y + run);
+            continue;
+          },
+          '!' => break 'lines,
+          _ => return Err(format!("Unsupported character in RLE: '{}'", c)),
+        };
+        if state != 0 {
+          for i in 0..run {
+            cells.push((pos + Vec2(i, 0), state));
+          }
+        }
+        pos.x += run;
+        size.x = size.x.max(pos.x);
+      }
+    }
+    if !header_found {
+      return Err("RLE pattern has no header line".to_string());
+    }
+    size.y = cells.iter().map(|&(pos, _)| pos.y + 1).max().unwrap_or(0);
+    Ok(Pattern{name: name, size: size, kind: kind, cells: cells})
+  }
+
+  /// Parses a Wireworld .wi pattern: an optional "width height" line, followed by rows of
+  /// `#` (wire), `@` (electron head), `~` (electron tail) and spaces or `.` (empty).
+  pub fn parse_wi(name: String, text: &str) -> Result<Pattern, String> {
+    let mut lines = text.lines().peekable();
+    let has_header = match lines.peek() {
+      Some(line) => line.split_whitespace().all(|x| x.parse::<i32>().is_ok()) && !line.trim().is_empty(),
+      None => false,
+    };
+    if has_header {
+      lines.next();
+    }
+
+    let mut cells = Vec::new();
+    let mut size = Vec2(0, 0);
+    for (y, line) in lines.enumerate() {
+      for (x, c) in line.chars().enumerate() {
+        let state = match c {
+          ' ' | '.' => 0,
+          '@' => 1,
+          '~' => 2,
+          '#' => 3,
+          '\r' => continue,
+          _ => return Err(format!("Unsupported character in Wireworld pattern: '{}'", c)),
+        };
+        if state != 0 {
+          let pos = Vec2(x as i32, y as i32);
+          cells.push((pos, state));
+          size = Vec2(size.x.max(pos.x + 1), size.y.max(pos.y + 1));
+        }
+      }
+    }
+    Ok(Pattern{name: name, size: size, kind: PatternKind::Wireworld, cells: cells})
+  }
+
+  /// Places the pattern centered on the given cell. Cells outside the grid are skipped.
+  pub fn stamp(&self, grid: &mut Grid, center: Vec2<i32>) {
+    let life_type = match self.kind {
+      PatternKind::Life(Some(rule)) => grid.life_type_for_rule(rule).unwrap_or(LifeType::Conway),
+      _ => LifeType::Conway,
+    };
+    let top_left = center - self.size/2;
+    for &(pos, state) in self.cells.iter() {
+      let typ = match (&self.kind, state) {
+        (&PatternKind::Life(_), _) => CellType::LifeOn(life_type),
+        (&PatternKind::Wireworld, 1) => CellType::ElectronHead(WireType::Normal),
+        (&PatternKind::Wireworld, 2) => CellType::ElectronTail(WireType::Normal, tail_lifetime),
+        (&PatternKind::Wireworld, _) => CellType::Wire(WireType::Normal),
+      };
+      if grid.in_range(top_left + pos) {
+        grid[top_left + pos].typ = typ;
+      }
+    }
+  }
+}
@@ -0,0 +1,104 @@
+use rand::Rng;
+
+use vecmat::*;
+
+use world::*;
+
+
+// Steam this close to the top of the world turns into cloud. "Up" follows gravity; see Grid::height.
+const cloud_height: i32 = 15;
+// The chance per tick of a water cell open to the air evaporating a little, at ambient temperature and no humidity
+const evaporation_rate: f64 = 0.002;
+// How much water evaporates at a time
+const evaporation_amount: f64 = 0.05;
+// The chance per tick of a full cloud cell precipitating, at full humidity
+const precipitation_rate: f64 = 0.01;
+// How much water is in a rain drop
+const rain_amount: f64 = 0.2;
+// How much cloud it takes to make a snowflake
+const snow_amount: f64 = 0.5;
+
+/// Global weather settings. Weather moves water in a cycle: exposed water evaporates into steam,
+/// steam that rises to the top of the world forms clouds, and clouds rain (or snow) back down.
+#[derive(Copy, Clone)]
+pub struct Weather {
+  pub enabled: bool,
+  // 0.0-1.0. Humid air evaporates less water and rains more.
+  pub humidity: f64,
+  // In degrees. The air temperature: clouds snow instead of raining at or below freezing. Water
+  // evaporates according to whichever is hotter, the air or whatever is heating it (see Grid::heat).
+  pub temperature: f64,
+}
+
+impl Weather {
+  pub fn new() -> Weather {
+    Weather{enabled: false, humidity: 0.5, temperature: ambient_temp}
+  }
+
+  /// The chance per tick of exposed water evaporating, given how hot the water is locally.
+  /// Nothing evaporates at or below freezing.
+  fn evaporation_chance(self, heat: f64) -> f64 {
+    let temperature = self.temperature.max(heat);
+    evaporation_rate * (1.0 - self.humidity) * (temperature / ambient_temp).max(0.0)
+  }
+}
+
+/// The hottest of a cell and its neighbours. Water doesn't conduct heat itself, so this is how
+/// hot metal or a fire next to it warms it up.
+fn local_heat(grid: &Grid, pos: Vec2<i32>) -> f64 {
+  [Vec2(0, 0), Vec2(0, -1), Vec2(0, 1), Vec2(-1, 0), Vec2(1, 0)].iter()
+    .filter_map(|&dir| grid.resolve(pos+dir))
+    .map(|other| grid.heat(other))
+    .fold(grid.heat(pos), f64::max)
+}
+
+/// Runs one tick of evaporation, cloud forming and precipitation
+pub fn update_weather<R: Rng>(grid: &mut Grid, rng: &mut R) {
+  let weather = grid.weather;
+  let top = grid.top_height();
+  for y in 0..grid.size.y {
+    for x in 0..grid.size.x {
+      let pos = Vec2(x, y);
+      // Nothing rises or falls without gravity
+      let down = match grid.down_at(pos) {
+        Some(down) => down,
+        None => continue,
+      };
+      match grid[pos].typ {
+        CellType::Fluid(FluidType::Water, amount) => {
+          let above = match grid.resolve(pos-down) {
+            Some(above) if grid[above].typ == CellType::Empty => above,
+            _ => continue,
+          };
+          if rng.gen::<f64>() < weather.evaporation_chance(local_heat(grid, pos)) {
+            let evaporated = amount.min(evaporation_amount);
+            grid[pos].typ = CellType::Fluid(FluidType::Water, amount - evaporated);
+            grid[above].typ = CellType::Fluid(FluidType::Steam, evaporated);
+          }
+        },
+        CellType::Fluid(FluidType::Steam, amount) if grid.height(pos) > top - cloud_height as f64 => {
+          grid[pos].typ = CellType::Fluid(FluidType::Cloud, amount);
+        },
+        CellType::Fluid(FluidType::Cloud, amount) => {
+          let below = match grid.resolve(pos+down) {
+            Some(below) if grid[below].typ == CellType::Empty => below,
+            _ => continue,
+          };
+          if rng.gen::<f64>() < precipitation_rate * weather.humidity * amount.min(1.0) {
+            let (drop, used) = if weather.temperature <= 0.0 {
+              (CellType::Granular(GranularType::Snow, false, false, 0.0), snow_amount)
+            } else {
+              (CellType::Fluid(FluidType::Water, amount.min(rain_amount)), amount.min(rain_amount))
+            };
+            if used > amount {
+              continue;
+            }
+            grid[pos].typ = CellType::Fluid(FluidType::Cloud, amount - used);
+            grid[below].typ = drop;
+          }
+        },
+        _ => (),
+      }
+    }
+  }
+}
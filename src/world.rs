@@ -1,12 +1,13 @@
 extern crate glium;
 
-use num::range_inclusive;
-
 use std::ops::{Index, IndexMut};
 use rand::Rng;
 use std::iter::repeat;
 use std::collections::*;
 use std::borrow::Cow;
+use std::cmp;
+use std::mem;
+use std::f64::consts::PI;
 
 use vecmat::*;
 use vecmat::num_ext::*;
@@ -24,19 +25,29 @@ use gui::util::*;
 use gui::widgets::*;
 use gui::window::*;
 
+use explosion::*;
+use particle::*;
+use weather::*;
+use light::*;
+
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
-pub enum SolidType {Wall, Ice}
+pub enum SolidType {Wall, Ice, Metal, Obsidian, Glass}
 
 #[derive(Copy, Clone)]
 pub struct Solid {
   typ: SolidType,
   name: &'static str,
   color: Color3,
+  // How much of the temperature difference with each neighbour is conducted per tick (0.0-0.25)
+  heat_conductivity: f64,
+  transparency: f64, //0.0-1.0; the fraction of light that gets through each cell
+  acid_resistance: f64, //0.0-1.0; 1.0 is immune
+  hardness: f64, // How much blast energy it takes to break
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
-pub enum GranularType {Sand, Dirt, Snow, Nitro}
+pub enum GranularType {Sand, Dirt, Snow, Nitro, Stone, Ash, GrassSeed, ShrubSeed, TreeSeed}
 
 #[derive(Copy, Clone)]
 pub struct Granular {
@@ -46,13 +57,19 @@ pub struct Granular {
   granularity_90: f64, //0.0-1.0
   horizontal_spread: f64, //0.0-1.0
   spread_speed: f64,
-  // TODO: support fall_speed < 1.0
-  fall_speed: f64, //0.0-2.0
+  fall_speed: f64, // Cells per tick; see cells_this_tick
   color: Color3,
+  density: f64, // Grains sink through lighter fluids, and float on denser ones
+  erodibility: f64, //0.0-1.0; how easily flowing water washes it away
+  absorbency: f64, // The fraction of each adjacent water cell soaked up per tick
+  sprouts: Option<PlantType>, // Seeds grow into this plant; see sprout
+  acid_resistance: f64, //0.0-1.0; 1.0 is immune
+  flammability: Option<Flammability>,
+  hardness: f64, // How much blast energy it takes to break
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
-pub enum FluidType {Water, Oil, Methane, Steam, Cement}
+pub enum FluidType {Water, Oil, Methane, Steam, Cement, Lava, Acid, Smoke, Mud, Cloud}
 
 #[derive(Copy, Clone)]
 pub struct Fluid {
@@ -60,13 +77,197 @@ pub struct Fluid {
   // TODO: not all of these properties are implemented
   name: &'static str,
   horizontal_spread: f64, //0.0-1.0
-  fall_speed: f64, //0.0-2.0
+  fall_speed: f64, // Cells per tick; see cells_this_tick
+  viscosity: f64, //0.0-1.0; how much it resists spreading sideways
   compressibility: f64,
   color: Color3,
   density: f64,
-  // Used to determine whether the fluid falls or rises
-  down_dir: Vec2<i32>,
-  up_dir: Vec2<i32>,
+  // Gases diffuse to fill their container instead of pooling; see the gas branch of Cell::simulate
+  gas: bool,
+  transparency: f64, //0.0-1.0; the fraction of light that gets through each cell
+  acid_resistance: f64, //0.0-1.0; 1.0 is immune
+  flammability: Option<Flammability>,
+  hardness: f64, // How much blast energy it takes to break
+}
+
+/// How a material burns
+#[derive(Copy, Clone)]
+pub struct Flammability {
+  ignition_chance: f64, // The chance that fire next to it sets it alight
+  burn_time: i32, // How many ticks it burns in place for
+  smoke: f64, // The chance per tick of giving off smoke while burning
+  ash: f64, // The chance of leaving ash behind when it burns out
+}
+
+/// Something that's burning
+#[derive(Copy, Clone, PartialEq)]
+pub enum Fuel {
+  Plant,
+  Granular(GranularType),
+  Fluid(FluidType),
+  // Part of a plant grown from seed
+  Grown(PlantType),
+}
+
+const plant_flammability: Flammability = Flammability{ignition_chance: 0.5, burn_time: 30, smoke: 0.05, ash: 0.5};
+
+impl Fuel {
+  pub fn flammability(self, grid: &Grid) -> Flammability {
+    match self {
+      Fuel::Plant => plant_flammability,
+      Fuel::Granular(typ) => grid.granular[&typ].flammability.unwrap(),
+      Fuel::Fluid(typ) => grid.fluid[&typ].flammability.unwrap(),
+      Fuel::Grown(typ) => grid.plants[&typ].flammability,
+    }
+  }
+  /// The cell this fuel becomes once it's been set alight
+  pub fn ignite(self, grid: &Grid) -> CellType {
+    CellType::Burning(self, self.flammability(grid).burn_time)
+  }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PlantType {Grass, Shrub, Tree}
+
+/// A species of plant grown from seed. Its stem grows up from the seed and its roots grow down
+/// into the soil, and both branch and put out leaves at random as they go.
+#[derive(Copy, Clone)]
+pub struct PlantSpecies {
+  typ: PlantType,
+  name: &'static str,
+  height: i32, // How many cells the stem grows from the seed
+  root_depth: i32, // How many cells the roots grow from the seed
+  growth_rate: f64, // The chance per tick of a growing tip growing another cell
+  branch_chance: f64, // The chance of each new cell of stem or root branching off
+  leaf_chance: f64, // The chance of each new cell of stem putting out a leaf
+  seed_chance: f64, // The chance per tick of a leaf dropping a seed
+  seed: GranularType,
+  stem_color: Color3,
+  leaf_color: Color3,
+  flammability: Flammability,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LifeType {Classic, Conway, HighLife, DayNight, Seeds}
+
+#[derive(Copy, Clone)]
+pub struct Life {
+  typ: LifeType,
+  name: &'static str,
+  rule: LifeRule,
+  // Whether cells that aren't this species count as neighbours
+  count_nonlife: bool,
+  // Used in addition to `rule` when the neighbourhood has both this species and other cells
+  mixed_rule: LifeRule,
+  color: Color3,
+}
+
+/// A Life-like rule in B/S notation, e.g. "B3/S23" for Conway's Life.
+/// Bit n of each mask is set if n neighbours cause a birth/survival.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct LifeRule {
+  birth: u16,
+  survival: u16,
+}
+
+impl LifeRule {
+  pub fn parse(rule: &str) -> Result<LifeRule, String> {
+    let mut birth = None;
+    let mut survival = None;
+    for part in rule.split('/') {
+      let part = part.trim();
+      let mut chars = part.chars();
+      let mask = match chars.next() {
+        Some('B') | Some('b') => &mut birth,
+        Some('S') | Some('s') => &mut survival,
+        _ => return Err(format!("Invalid Life rule \"{}\": each part must start with B or S", rule)),
+      };
+      if mask.is_some() {
+        return Err(format!("Invalid Life rule \"{}\": \"{}\" is given twice", rule, part));
+      }
+      let mut bits = 0;
+      for c in chars {
+        match c.to_digit(10) {
+          Some(n) if n <= 8 => bits |= 1 << n,
+          _ => return Err(format!("Invalid Life rule \"{}\": '{}' isn't a neighbour count", rule, c)),
+        }
+      }
+      *mask = Some(bits);
+    }
+    match (birth, survival) {
+      (Some(birth), Some(survival)) => Ok(LifeRule{birth: birth, survival: survival}),
+      _ => Err(format!("Invalid Life rule \"{}\": it needs both a B and an S part", rule)),
+    }
+  }
+
+  pub fn births(self, neighbors: i32) -> bool {
+    self.birth & (1 << neighbors) != 0
+  }
+
+  pub fn survives(self, neighbors: i32) -> bool {
+    self.survival & (1 << neighbors) != 0
+  }
+}
+
+impl Life {
+  fn births(&self, neighbors: i32, some_nonlife: bool) -> bool {
+    self.rule.births(neighbors) || (some_nonlife && self.mixed_rule.births(neighbors))
+  }
+
+  fn survives(&self, neighbors: i32, some_life: bool, some_nonlife: bool) -> bool {
+    // When other cells count as neighbours, at least one of them must be this species
+    ((some_life || !self.count_nonlife) && self.rule.survives(neighbors)) ||
+      (some_life && some_nonlife && self.mixed_rule.survives(neighbors))
+  }
+}
+
+/// Counts the neighbours of a cell as seen by the given Life species.
+/// Returns the number of neighbours, and whether any of them are that species or other cells.
+fn life_neighbors(grid: &Grid, pos: Vec2<i32>, typ: LifeType) -> (i32, bool, bool) {
+  let count_nonlife = grid.life[&typ].count_nonlife;
+  let mut neighbors = 0;
+  let mut some_life = false;
+  let mut some_nonlife = false;
+  for neighbor in grid.moore(pos) {
+    match neighbor.typ {
+      CellType::Empty | CellType::LifeTurningOn(_) => (),
+      CellType::LifeOn(typ2) if typ2 == typ => {
+        some_life = true;
+        neighbors += 1;
+      },
+      _ => if count_nonlife {
+        some_nonlife = true;
+        neighbors += 1;
+      },
+    }
+  }
+  (neighbors, some_life, some_nonlife)
+}
+
+/// Returns the Life species that should be born in an empty cell, if any.
+/// If several species could be born, the one with the most neighbours wins.
+fn life_birth(grid: &Grid, pos: Vec2<i32>) -> Option<LifeType> {
+  // The number of neighbours of each species
+  let mut species: Vec<(LifeType, i32)> = Vec::new();
+  for neighbor in grid.moore(pos) {
+    if let CellType::LifeOn(typ) = neighbor.typ {
+      match species.iter().position(|&(typ2, _)| typ2 == typ) {
+        Some(i) => species[i].1 += 1,
+        None => species.push((typ, 1)),
+      }
+    }
+  }
+
+  let mut best = None;
+  let mut best_count = 0;
+  for (typ, count) in species {
+    let (neighbors, _, some_nonlife) = life_neighbors(grid, pos, typ);
+    if count > best_count && grid.life[&typ].births(neighbors, some_nonlife) {
+      best = Some(typ);
+      best_count = count;
+    }
+  }
+  best
 }
 
 // pub type TypeId = u16;
@@ -74,30 +275,147 @@ pub struct Fluid {
 pub const virus_lifetime: i32 = 10;
 pub const tail_lifetime: i32 = 2;
 
+// Temperatures are in degrees Celsius
+pub const ambient_temp: f64 = 20.0;
+const boiling_temp: f64 = 100.0;
+const metal_ignition_temp: f64 = 900.0;
+// The fraction of the temperature difference that conductors lose to non-conducting neighbours per tick
+const heat_loss: f64 = 0.01;
+
+// How much smoke fades per tick
+const smoke_fade: f64 = 0.005;
+
+// The chance per tick that acid dissolves a neighbour with no acid resistance
+const acid_strength: f64 = 0.3;
+// How much acid is used up by each cell it dissolves
+const acid_per_dissolve: f64 = 0.2;
+// How long doors and valves stay switched after their last pulse, so that a clock can hold them
+pub const power_hold_time: i32 = 8;
+// How much moisture a powder loses per tick next to something hot
+const drying_rate: f64 = 0.02;
+// How much of the extra chance of settling a fully wet powder gets
+const wet_cohesion: f64 = 0.8;
+// Dirt this wet turns into mud
+const mud_moisture: f64 = 0.95;
+// Mud dried out by heat turns back into dirt this wet
+const dried_mud_moisture: f64 = 0.9;
+// Scales the chance of flowing liquid picking up the powder under it
+const erosion_rate: f64 = 0.5;
+// Gases lighter than this rise and heavier ones sink
+const air_density: f64 = 0.6;
+// Gas cells with less than twice this much don't split when they expand
+const gas_min_split: f64 = 0.05;
+// The chance of two different gases swapping places when they meet
+const gas_mixing: f64 = 0.3;
+// The chance per tick of steam away from heat condensing
+const steam_condensation: f64 = 0.005;
+// The most surface cells pressure can move in each body of liquid per tick
+const pressure_moves: usize = 4;
+// How many empty cells have to be below a grain before it falls freely as a particle
+const freefall_depth: i32 = 4;
+// How much water a plant part holds when its roots have just drunk. It loses one per tick, and
+// passes on what it has left to the parts next to it; see plant_water.
+const max_plant_water: i32 = 500;
+// Plant parts only grow while they have at least this much water
+const grow_water: i32 = 300;
+// How wet the dirt under a seed has to be for it to sprout
+const sprout_moisture: f64 = 0.1;
+// The chance per tick of a seed in wet enough dirt sprouting
+const sprout_chance: f64 = 0.01;
+// How much moisture a root takes out of the soil each time it drinks
+const root_drink: f64 = 0.002;
+// Stems only grow where at least this much light reaches them; see light::update_light
+const grow_light: f64 = 0.3;
+// The chance per tick of a dead plant part rotting away
+const plant_decay: f64 = 0.002;
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum CellType {
   Empty,
   // Wall,
   Solid(SolidType),
-  Granular(GranularType, bool, bool),
+  // The flags are whether it's settled at 45 and 90 degrees, and the number is its moisture (0.0-1.0)
+  Granular(GranularType, bool, bool, f64),
   Fluid(FluidType, f64),
-  WaterGenerator,
-  SandGenerator,
-  Destroyer,
+  // The flag is set for variants that only act while powered (see Grid::powered)
+  WaterGenerator(bool),
+  SandGenerator(bool),
+  Destroyer(bool),
   Plant,
   Fire,
-  Torch,
-  ExplodingNitro(Vec2<i32>),
-  LifeOn,
-  LifeTurningOn,
+  Torch(bool),
+  // Nitro that's been set off by an explosion, and explodes on the next tick
+  ExplodingNitro,
+  LifeOn(LifeType),
+  LifeTurningOn(LifeType),
   Wire(WireType),
   ElectronHead(WireType),
   ElectronTail(WireType, i32),
   Eater,
   Fuse(bool),
   Virus(i32),
+  /// Blocks everything while closed, and lets material fall or flow through while open.
+  /// Opens when powered, and stays open for the given number of ticks.
+  Door(i32),
+  /// Lets fluids, but not powders, flow through it. Closes when powered, and stays
+  /// closed for the given number of ticks.
+  Valve(i32),
+  /// Signals to adjacent wires while its condition holds; the flag is set when it's active
+  Sensor(SensorType, bool),
+  /// A flammable cell that's on fire, with the number of ticks left until it burns out
+  Burning(Fuel, i32),
+  /// Parts of a plant grown from a seed. The first number is how much water the part has left,
+  /// and it's dead once that runs out. The second is how many more cells a growing tip of root
+  /// or stem will grow, and is 0 for parts that have stopped growing.
+  Root(PlantType, i32, i32),
+  Stem(PlantType, i32, i32),
+  Leaf(PlantType, i32),
+  /// Lights up when powered, and stays lit for the given number of ticks
+  Lamp(i32),
+}
+
+/// What a cell is made of, ignoring any state it carries, such as a fluid's amount or whether a
+/// door is open. Unlike the material ID, this doesn't depend on how cells are drawn.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Material {
+  Solid(SolidType),
+  Granular(GranularType),
+  Fluid(FluidType),
+  Other(mem::Discriminant<CellType>),
+}
+
+/// The condition a sensor checks in its Moore neighbourhood
+#[derive(Copy, Clone, PartialEq)]
+pub enum SensorType {
+  /// A cell made of the given material
+  Material(Material),
+  /// A cell of the given fluid holding more than the given amount
+  FluidLevel(FluidType, f64),
+  /// Fire or a burning fuse
+  Fire,
+  /// A live Life cell of any species
+  Life,
+}
+
+impl SensorType {
+  pub fn is_triggered_by(self, typ: CellType) -> bool {
+    match (self, typ) {
+      (SensorType::Material(material), typ) => typ.material() == material,
+      (SensorType::FluidLevel(fluid, threshold), CellType::Fluid(fluid2, amount)) => fluid == fluid2 && amount > threshold,
+      (SensorType::FluidLevel(..), _) => false,
+      (SensorType::Fire, CellType::Fire) | (SensorType::Fire, CellType::Fuse(true)) |
+        (SensorType::Fire, CellType::Burning(..)) => true,
+      (SensorType::Fire, _) => false,
+      (SensorType::Life, CellType::LifeOn(_)) => true,
+      (SensorType::Life, _) => false,
+    }
+  }
 }
 
+/// Electricity follows Wireworld-like rules: a wire becomes an electron head when an adjacent
+/// head can signal to it, then becomes a tail for `tail_lifetime` ticks before it's a wire again.
+/// Gates take their inputs from adjacent `Input` heads and only signal to adjacent `Output`
+/// wires, so each input line should touch a gate with exactly one cell.
 #[derive(Copy, Clone, PartialEq)]
 pub enum WireType {
   Normal,
@@ -105,11 +423,52 @@ pub enum WireType {
   Output,
   Nor(bool),
   SignalEmitter,
+  /// Metal conducts like a normal wire, but is a solid rather than a wire when it isn't carrying electrons
+  Metal,
+  /// Fires when at least two adjacent inputs are heads
+  And,
+  /// Fires when at least one adjacent input is a head
+  Or,
+  /// Fires when exactly one adjacent input is a head
+  Xor,
+  /// Passes signals from normal and input wires on to output wires, but never backwards
+  Diode,
+  /// Fires every `period` ticks (at least `tail_lifetime+2`), ignoring incoming signals.
+  /// The second value counts down the ticks until it fires next.
+  Clock(i32, i32),
+  /// Like a diode, but fires the given number of ticks later than a normal wire would.
+  /// Further signals are ignored while it's waiting. The second value counts down the ticks
+  /// until it fires, and is 0 when it's idle.
+  Delay(i32, i32),
 }
 
 impl WireType {
+  /// Whether a head on a wire of this type makes an adjacent wire of type `out` fire on the next tick.
+  /// Gates only listen to inputs and only signal to outputs, diodes and delays only pass signals
+  /// forwards, and clocks ignore everything.
   pub fn can_signal_to(self, out: WireType) -> bool {
-    match (self, out) {
+    let as_wire = |wtype| if wtype == WireType::Metal {WireType::Normal} else {wtype};
+    match (as_wire(self), as_wire(out)) {
+      (_, WireType::Clock(..)) => false,
+      (WireType::Clock(..), _) => true,
+
+      (WireType::Input, WireType::And) => true,
+      (WireType::Input, WireType::Or) => true,
+      (WireType::Input, WireType::Xor) => true,
+      (WireType::And, WireType::Output) => true,
+      (WireType::Or, WireType::Output) => true,
+      (WireType::Xor, WireType::Output) => true,
+      (WireType::And, _) | (WireType::Or, _) | (WireType::Xor, _) => false,
+      (_, WireType::And) | (_, WireType::Or) | (_, WireType::Xor) => false,
+
+      (WireType::Normal, WireType::Diode) | (WireType::Input, WireType::Diode) => true,
+      (WireType::Diode, WireType::Output) => true,
+      (WireType::Diode, _) | (_, WireType::Diode) => false,
+
+      (WireType::Normal, WireType::Delay(..)) | (WireType::Input, WireType::Delay(..)) => true,
+      (WireType::Delay(..), WireType::Output) => true,
+      (WireType::Delay(..), _) | (_, WireType::Delay(..)) => false,
+
       (WireType::Output, WireType::Input) => false,
       (WireType::Output, _) => true,
       (WireType::Input, WireType::Nor(_)) => true,
@@ -136,6 +495,9 @@ impl WireType {
   pub fn transition_to(self) -> Self {
     match self {
       WireType::Nor(_) => WireType::Nor(false),
+      // The head and tail take up tail_lifetime+1 ticks, and it takes another tick to fire again
+      WireType::Clock(period, _) => WireType::Clock(period, (period - tail_lifetime - 2).max(0)),
+      WireType::Delay(delay, _) => WireType::Delay(delay, 0),
       x => x
     }
   }
@@ -170,60 +532,418 @@ impl CellType {
     match self {
       CellType::Empty => "empty",
       CellType::Solid(typ) => grid.solid[&typ].name,
-      CellType::Granular(typ, _, _) => grid.granular[&typ].name,
+      CellType::Granular(typ, _, _, _) => grid.granular[&typ].name,
       CellType::Fluid(typ, _) => grid.fluid[&typ].name,
-      CellType::WaterGenerator => "water generator",
-      CellType::SandGenerator => "sand generator",
-      CellType::Destroyer => "destroyer",
+      CellType::WaterGenerator(false) => "water generator",
+      CellType::WaterGenerator(true) => "powered water generator",
+      CellType::SandGenerator(false) => "sand generator",
+      CellType::SandGenerator(true) => "powered sand generator",
+      CellType::Destroyer(false) => "destroyer",
+      CellType::Destroyer(true) => "powered destroyer",
       CellType::Plant => "plant",
       CellType::Fire => "fire",
-      CellType::Torch => "torch",
-      CellType::ExplodingNitro(..) => "exploding nitro",
-      CellType::LifeOn => "life cell",
-      CellType::LifeTurningOn => "life cell",
-      CellType::Wire(WireType::Normal) => "wire",
+      CellType::Torch(false) => "torch",
+      CellType::Torch(true) => "powered torch",
+      CellType::ExplodingNitro => "exploding nitro",
+      CellType::LifeOn(typ) => grid.life[&typ].name,
+      CellType::LifeTurningOn(typ) => grid.life[&typ].name,
+      // Metal is only a wire type while it carries electrons, so there's never a metal wire
+      CellType::Wire(WireType::Normal) | CellType::Wire(WireType::Metal) => "wire",
       CellType::Wire(WireType::Input) => "wire input",
       CellType::Wire(WireType::Output) => "wire output",
       CellType::Wire(WireType::Nor(_)) => "wire nor",
       CellType::Wire(WireType::SignalEmitter) => "signal emitter",
+      CellType::Wire(WireType::And) => "wire and",
+      CellType::Wire(WireType::Or) => "wire or",
+      CellType::Wire(WireType::Xor) => "wire xor",
+      CellType::Wire(WireType::Diode) => "diode",
+      CellType::Wire(WireType::Clock(..)) => "clock",
+      CellType::Wire(WireType::Delay(..)) => "delay",
       CellType::ElectronHead(_) => "electron head",
       CellType::ElectronTail(_, _) => "electron tail",
       CellType::Eater => "eater",
       CellType::Fuse(_) => "fuse",
       CellType::Virus(_) => "virus",
+      CellType::Door(_) => "door",
+      CellType::Valve(_) => "valve",
+      CellType::Sensor(SensorType::Material(_), _) => "material sensor",
+      CellType::Sensor(SensorType::FluidLevel(..), _) => "fluid level sensor",
+      CellType::Sensor(SensorType::Fire, _) => "fire sensor",
+      CellType::Sensor(SensorType::Life, _) => "life sensor",
+      CellType::Burning(..) => "burning",
+      CellType::Root(..) => "root",
+      CellType::Stem(typ, _, _) => grid.plants[&typ].name,
+      CellType::Leaf(..) => "leaf",
+      CellType::Lamp(_) => "lamp",
+    }
+  }
+}
+
+impl CellType {
+  pub fn material(self) -> Material {
+    match self {
+      CellType::Solid(typ) => Material::Solid(typ),
+      // Metal is still metal while it's carrying a signal
+      CellType::ElectronHead(WireType::Metal) | CellType::ElectronTail(WireType::Metal, _) => Material::Solid(SolidType::Metal),
+      CellType::Granular(typ, _, _, _) => Material::Granular(typ),
+      CellType::Fluid(typ, _) => Material::Fluid(typ),
+      _ => Material::Other(mem::discriminant(&self)),
+    }
+  }
+
+  /// The wire type of wires and electrons, which is preserved as electrons pass through them
+  pub fn wire_type(self) -> Option<WireType> {
+    match self {
+      CellType::Wire(wtype) | CellType::ElectronHead(wtype) | CellType::ElectronTail(wtype, _) => Some(wtype),
+      CellType::Solid(SolidType::Metal) => Some(WireType::Metal),
+      _ => None,
+    }
+  }
+
+  /// The temperature of cells that produce heat
+  pub fn heat_source(self) -> Option<f64> {
+    match self {
+      CellType::Fire | CellType::Fuse(true) | CellType::Burning(..) => Some(600.0),
+      CellType::Torch(_) => Some(1000.0),
+      CellType::Fluid(FluidType::Lava, _) => Some(1200.0),
+      _ => None,
+    }
+  }
+
+  pub fn fuel(self, grid: &Grid) -> Option<Fuel> {
+    match self {
+      CellType::Plant => Some(Fuel::Plant),
+      CellType::Root(typ, _, _) | CellType::Stem(typ, _, _) | CellType::Leaf(typ, _) => Some(Fuel::Grown(typ)),
+      CellType::Granular(typ, _, _, _) if grid.granular[&typ].flammability.is_some() => Some(Fuel::Granular(typ)),
+      CellType::Fluid(typ, _) if grid.fluid[&typ].flammability.is_some() => Some(Fuel::Fluid(typ)),
+      _ => None,
+    }
+  }
+
+  /// How much blast energy it takes to break a cell (see explosion::explode)
+  pub fn hardness(self, grid: &Grid) -> f64 {
+    match self {
+      CellType::Empty | CellType::Fire | CellType::ExplodingNitro | CellType::Burning(..) => 0.0,
+      CellType::Solid(typ) => grid.solid[&typ].hardness,
+      CellType::ElectronHead(WireType::Metal) | CellType::ElectronTail(WireType::Metal, _) => grid.solid[&SolidType::Metal].hardness,
+      CellType::Granular(typ, _, _, _) => grid.granular[&typ].hardness,
+      CellType::Fluid(typ, _) => grid.fluid[&typ].hardness,
+      CellType::Plant | CellType::Fuse(_) | CellType::LifeOn(_) | CellType::LifeTurningOn(_) | CellType::Virus(_) |
+        CellType::Leaf(..) => 0.2,
+      CellType::Root(..) | CellType::Stem(..) | CellType::Lamp(_) => 0.5,
+      CellType::Wire(_) | CellType::ElectronHead(_) | CellType::ElectronTail(..) | CellType::Sensor(..) => 1.0,
+      CellType::Door(_) | CellType::Valve(_) => 3.0,
+      CellType::WaterGenerator(_) | CellType::SandGenerator(_) | CellType::Destroyer(_) | CellType::Torch(_) |
+        CellType::Eater => 4.0,
+    }
+  }
+
+  /// How well a cell resists being dissolved by acid, from 0.0-1.0, where 1.0 is immune
+  pub fn acid_resistance(self, grid: &Grid) -> f64 {
+    match self {
+      CellType::Empty => 1.0,
+      CellType::Solid(typ) => grid.solid[&typ].acid_resistance,
+      CellType::ElectronHead(WireType::Metal) | CellType::ElectronTail(WireType::Metal, _) => grid.solid[&SolidType::Metal].acid_resistance,
+      CellType::Granular(typ, _, _, _) => grid.granular[&typ].acid_resistance,
+      CellType::Fluid(typ, _) => grid.fluid[&typ].acid_resistance,
+      CellType::Plant | CellType::Fuse(_) | CellType::LifeOn(_) | CellType::LifeTurningOn(_) | CellType::Virus(_) |
+        CellType::Root(..) | CellType::Stem(..) | CellType::Leaf(..) => 0.2,
+      CellType::Wire(_) | CellType::ElectronHead(_) | CellType::ElectronTail(..) | CellType::Door(_) | CellType::Valve(_) |
+        CellType::Sensor(..) | CellType::Lamp(_) => 0.8,
+      CellType::WaterGenerator(_) | CellType::SandGenerator(_) | CellType::Destroyer(_) | CellType::Torch(_) |
+        CellType::Eater => 0.95,
+      CellType::Fire | CellType::ExplodingNitro | CellType::Burning(..) => 1.0,
+    }
+  }
+
+  pub fn heat_conductivity(self, grid: &Grid) -> f64 {
+    match self {
+      CellType::Solid(typ) => grid.solid[&typ].heat_conductivity,
+      CellType::ElectronHead(WireType::Metal) | CellType::ElectronTail(WireType::Metal, _) => grid.solid[&SolidType::Metal].heat_conductivity,
+      _ => 0.0,
+    }
+  }
+
+  /// How brightly cells that give off light shine, where 1.0 is as bright as full daylight.
+  /// Powered torches only shine while they're burning, like the fire they give off.
+  pub fn light_source(self, grid: &Grid, pos: Vec2<i32>) -> Option<f64> {
+    match self {
+      CellType::Torch(false) => Some(1.0),
+      CellType::Torch(true) if grid.powered(pos) => Some(1.0),
+      CellType::Lamp(lit) if lit > 0 => Some(1.0),
+      CellType::Fire | CellType::Burning(..) => Some(0.9),
+      CellType::Fluid(FluidType::Lava, _) => Some(0.8),
+      CellType::Fuse(true) => Some(0.5),
+      _ => None,
+    }
+  }
+
+  /// The fraction of light that gets through a cell (0.0-1.0). Powders and most other cells block it.
+  pub fn transparency(self, grid: &Grid) -> f64 {
+    match self {
+      CellType::Empty | CellType::Fire => 1.0,
+      CellType::Solid(typ) => grid.solid[&typ].transparency,
+      CellType::Fluid(typ, _) => grid.fluid[&typ].transparency,
+      _ => 0.0,
+    }
+  }
+
+  /// The index of this cell's colour in the palette texture.
+  /// Cells that only differ in their payload (see `shade` and `brightness`) share a material ID.
+  pub fn material_id(self) -> u8 {
+    match self {
+      CellType::Empty => 0,
+      CellType::Solid(SolidType::Wall) => 1,
+      CellType::Solid(SolidType::Ice) => 2,
+      CellType::Granular(GranularType::Sand, _, _, _) => 3,
+      CellType::Granular(GranularType::Dirt, _, _, _) => 4,
+      CellType::Granular(GranularType::Snow, _, _, _) => 5,
+      CellType::Granular(GranularType::Nitro, _, _, _) => 6,
+      CellType::Fluid(FluidType::Water, _) => 7,
+      CellType::Fluid(FluidType::Oil, _) => 8,
+      CellType::Fluid(FluidType::Methane, _) => 9,
+      CellType::Fluid(FluidType::Steam, _) => 10,
+      CellType::Fluid(FluidType::Cement, _) => 11,
+      CellType::WaterGenerator(false) => 12,
+      CellType::SandGenerator(false) => 13,
+      CellType::Destroyer(false) => 14,
+      CellType::Plant => 15,
+      CellType::Fire => 16,
+      CellType::Torch(false) => 17,
+      CellType::ExplodingNitro => 18,
+      CellType::LifeOn(LifeType::Classic) => 19,
+      CellType::LifeTurningOn(LifeType::Classic) => 20,
+      CellType::Wire(WireType::Normal) | CellType::Wire(WireType::Metal) => 21,
+      CellType::Wire(WireType::Input) => 22,
+      CellType::Wire(WireType::Output) => 23,
+      CellType::Wire(WireType::Nor(false)) => 24,
+      CellType::Wire(WireType::Nor(true)) => 25,
+      CellType::Wire(WireType::SignalEmitter) => 26,
+      CellType::ElectronHead(_) => 27,
+      CellType::ElectronTail(_, _) => 28,
+      CellType::Eater => 29,
+      CellType::Fuse(false) => 30,
+      CellType::Fuse(true) => 31,
+      CellType::Virus(_) => 32,
+      CellType::LifeOn(LifeType::Conway) => 33,
+      CellType::LifeTurningOn(LifeType::Conway) => 34,
+      CellType::LifeOn(LifeType::HighLife) => 35,
+      CellType::LifeTurningOn(LifeType::HighLife) => 36,
+      CellType::LifeOn(LifeType::DayNight) => 37,
+      CellType::LifeTurningOn(LifeType::DayNight) => 38,
+      CellType::LifeOn(LifeType::Seeds) => 39,
+      CellType::LifeTurningOn(LifeType::Seeds) => 40,
+      CellType::Wire(WireType::And) => 41,
+      CellType::Wire(WireType::Or) => 42,
+      CellType::Wire(WireType::Xor) => 43,
+      CellType::Wire(WireType::Diode) => 44,
+      CellType::Wire(WireType::Clock(..)) => 45,
+      CellType::Wire(WireType::Delay(..)) => 46,
+      CellType::WaterGenerator(true) => 47,
+      CellType::SandGenerator(true) => 48,
+      CellType::Destroyer(true) => 49,
+      CellType::Torch(true) => 50,
+      CellType::Door(0) => 51,
+      CellType::Door(_) => 52,
+      CellType::Valve(0) => 53,
+      CellType::Valve(_) => 54,
+      CellType::Sensor(SensorType::Material(_), false) => 55,
+      CellType::Sensor(SensorType::FluidLevel(..), false) => 56,
+      CellType::Sensor(SensorType::Fire, false) => 57,
+      CellType::Sensor(SensorType::Life, false) => 58,
+      CellType::Sensor(_, true) => 59,
+      CellType::Solid(SolidType::Metal) => 60,
+      CellType::Fluid(FluidType::Lava, _) => 61,
+      CellType::Granular(GranularType::Stone, _, _, _) => 62,
+      CellType::Solid(SolidType::Obsidian) => 63,
+      CellType::Fluid(FluidType::Acid, _) => 64,
+      CellType::Solid(SolidType::Glass) => 65,
+      CellType::Burning(..) => 66,
+      CellType::Fluid(FluidType::Smoke, _) => 67,
+      CellType::Granular(GranularType::Ash, _, _, _) => 68,
+      CellType::Fluid(FluidType::Mud, _) => 69,
+      CellType::Fluid(FluidType::Cloud, _) => 70,
+      CellType::Granular(GranularType::GrassSeed, _, _, _) => 71,
+      CellType::Granular(GranularType::ShrubSeed, _, _, _) => 72,
+      CellType::Granular(GranularType::TreeSeed, _, _, _) => 73,
+      CellType::Root(..) => 74,
+      CellType::Stem(PlantType::Grass, _, _) => 75,
+      CellType::Stem(PlantType::Shrub, _, _) => 76,
+      CellType::Stem(PlantType::Tree, _, _) => 77,
+      CellType::Leaf(PlantType::Grass, _) => 78,
+      CellType::Leaf(PlantType::Shrub, _) => 79,
+      CellType::Leaf(PlantType::Tree, _) => 80,
+      CellType::Lamp(0) => 81,
+      CellType::Lamp(_) => 82,
+    }
+  }
+
+  /// How strongly the palette colour is blended over the background (0.0-1.0).
+  /// This is the per-cell payload that's uploaded alongside the material ID.
+  pub fn shade(self) -> f32 {
+    match self {
+      CellType::Fluid(_, amount) => (amount as f32/1.0).min(1.0).max(0.5),
+      // Plants fade as they dry out
+      CellType::Root(_, water, _) | CellType::Stem(_, water, _) | CellType::Leaf(_, water) =>
+        0.5 + water as f32 / max_plant_water as f32 * 0.5,
+      CellType::Virus(lifetime) => (lifetime.max(0) as f32 / (virus_lifetime+2) as f32).min(1.0),
+      _ => 1.0,
+    }
+  }
+
+  /// How much the palette colour is darkened towards black (0.0-1.0, where 1.0 is unchanged).
+  /// This is uploaded separately from the shade, which fades towards the background instead.
+  pub fn brightness(self) -> f32 {
+    match self {
+      // Wet powders are darker
+      CellType::Granular(_, _, _, moisture) => 1.0 - moisture as f32*0.4,
+      _ => 1.0,
     }
   }
 }
 
+/// One cell of every material, at full shade. Used to build the palette.
+pub fn materials() -> Vec<CellType> {
+  vec![
+    CellType::Empty,
+    CellType::Solid(SolidType::Wall),
+    CellType::Solid(SolidType::Ice),
+    CellType::Granular(GranularType::Sand, false, false, 0.0),
+    CellType::Granular(GranularType::Dirt, false, false, 0.0),
+    CellType::Granular(GranularType::Snow, false, false, 0.0),
+    CellType::Granular(GranularType::Nitro, false, false, 0.0),
+    CellType::Fluid(FluidType::Water, 1.0),
+    CellType::Fluid(FluidType::Oil, 1.0),
+    CellType::Fluid(FluidType::Methane, 1.0),
+    CellType::Fluid(FluidType::Steam, 1.0),
+    CellType::Fluid(FluidType::Cement, 1.0),
+    CellType::WaterGenerator(false),
+    CellType::SandGenerator(false),
+    CellType::Destroyer(false),
+    CellType::Plant,
+    CellType::Fire,
+    CellType::Torch(false),
+    CellType::ExplodingNitro,
+    CellType::LifeOn(LifeType::Classic),
+    CellType::LifeTurningOn(LifeType::Classic),
+    CellType::Wire(WireType::Normal),
+    CellType::Wire(WireType::Input),
+    CellType::Wire(WireType::Output),
+    CellType::Wire(WireType::Nor(false)),
+    CellType::Wire(WireType::Nor(true)),
+    CellType::Wire(WireType::SignalEmitter),
+    CellType::ElectronHead(WireType::Normal),
+    CellType::ElectronTail(WireType::Normal, tail_lifetime),
+    CellType::Eater,
+    CellType::Fuse(false),
+    CellType::Fuse(true),
+    CellType::Virus(virus_lifetime+2),
+    CellType::LifeOn(LifeType::Conway),
+    CellType::LifeTurningOn(LifeType::Conway),
+    CellType::LifeOn(LifeType::HighLife),
+    CellType::LifeTurningOn(LifeType::HighLife),
+    CellType::LifeOn(LifeType::DayNight),
+    CellType::LifeTurningOn(LifeType::DayNight),
+    CellType::LifeOn(LifeType::Seeds),
+    CellType::LifeTurningOn(LifeType::Seeds),
+    CellType::Wire(WireType::And),
+    CellType::Wire(WireType::Or),
+    CellType::Wire(WireType::Xor),
+    CellType::Wire(WireType::Diode),
+    CellType::Wire(WireType::Clock(0, 0)),
+    CellType::Wire(WireType::Delay(0, 0)),
+    CellType::WaterGenerator(true),
+    CellType::SandGenerator(true),
+    CellType::Destroyer(true),
+    CellType::Torch(true),
+    CellType::Door(0),
+    CellType::Door(power_hold_time),
+    CellType::Valve(0),
+    CellType::Valve(power_hold_time),
+    CellType::Sensor(SensorType::Material(Material::Solid(SolidType::Wall)), false),
+    CellType::Sensor(SensorType::FluidLevel(FluidType::Water, 0.0), false),
+    CellType::Sensor(SensorType::Fire, false),
+    CellType::Sensor(SensorType::Life, false),
+    CellType::Sensor(SensorType::Life, true),
+    CellType::Solid(SolidType::Metal),
+    CellType::Fluid(FluidType::Lava, 1.0),
+    CellType::Granular(GranularType::Stone, false, false, 0.0),
+    CellType::Solid(SolidType::Obsidian),
+    CellType::Fluid(FluidType::Acid, 1.0),
+    CellType::Solid(SolidType::Glass),
+    CellType::Burning(Fuel::Plant, 0),
+    CellType::Fluid(FluidType::Smoke, 1.0),
+    CellType::Granular(GranularType::Ash, false, false, 0.0),
+    CellType::Fluid(FluidType::Mud, 1.0),
+    CellType::Fluid(FluidType::Cloud, 1.0),
+    CellType::Granular(GranularType::GrassSeed, false, false, 0.0),
+    CellType::Granular(GranularType::ShrubSeed, false, false, 0.0),
+    CellType::Granular(GranularType::TreeSeed, false, false, 0.0),
+    CellType::Root(PlantType::Grass, max_plant_water, 0),
+    CellType::Stem(PlantType::Grass, max_plant_water, 0),
+    CellType::Stem(PlantType::Shrub, max_plant_water, 0),
+    CellType::Stem(PlantType::Tree, max_plant_water, 0),
+    CellType::Leaf(PlantType::Grass, max_plant_water),
+    CellType::Leaf(PlantType::Shrub, max_plant_water),
+    CellType::Leaf(PlantType::Tree, max_plant_water),
+    CellType::Lamp(0),
+    CellType::Lamp(power_hold_time),
+  ]
+}
+
 impl Cell {
   pub fn color(self, grid: &Grid) -> Color3 {
     match self.typ {
       CellType::Empty => background_color(),
       // CellType::Wall => Color3::rgb(0.5, 0.5, 0.5),
       CellType::Solid(typ) => grid.solid[&typ].color,
-      CellType::Granular(typ, _, _) => grid.granular[&typ].color,
+      CellType::Granular(typ, _, _, _) => grid.granular[&typ].color,
       CellType::Fluid(typ, amount) => grid.fluid[&typ].color.blend(background_color(), (amount as f32/1.0).min(1.0).max(0.5)),
-      CellType::WaterGenerator => Color3::rgb(0.0, 0.5, 1.0),
-      CellType::SandGenerator => Color3::rgb(0.9, 0.5, 0.2),
-      CellType::Destroyer => Color3::black(),
+      CellType::WaterGenerator(false) => Color3::rgb(0.0, 0.5, 1.0),
+      CellType::WaterGenerator(true) => Color3::rgb(0.0, 0.35, 0.7),
+      CellType::SandGenerator(false) => Color3::rgb(0.9, 0.5, 0.2),
+      CellType::SandGenerator(true) => Color3::rgb(0.65, 0.35, 0.15),
+      CellType::Destroyer(_) => Color3::black(),
       CellType::Plant => Color3::green()*0.6,
       CellType::Fire => Color3::rgb(1.0, 0.325, 0.0),
-      CellType::Torch => Color3::rgb(1.0, 0.1, 0.0),
-      CellType::ExplodingNitro(..) => Color3::rgb(0.3, 0.5, 0.3),
-      CellType::LifeOn => Color3::rgb(1.0, 1.0, 1.0),
-      CellType::LifeTurningOn => Color3::rgb(0.8, 0.8, 0.8),
-      CellType::Wire(WireType::Normal) => Color3::rgb(0.8, 0.4, 0.0),
+      CellType::Torch(false) => Color3::rgb(1.0, 0.1, 0.0),
+      CellType::Torch(true) => Color3::rgb(0.7, 0.1, 0.0),
+      CellType::ExplodingNitro => Color3::rgb(0.3, 0.5, 0.3),
+      CellType::LifeOn(typ) => grid.life[&typ].color,
+      CellType::LifeTurningOn(typ) => grid.life[&typ].color*0.8,
+      CellType::Wire(WireType::Normal) | CellType::Wire(WireType::Metal) => Color3::rgb(0.8, 0.4, 0.0),
       CellType::Wire(WireType::Input) => Color3::rgb(0.8, 0.4, 0.5),
       CellType::Wire(WireType::Output) => Color3::rgb(0.8, 0.9, 0.5),
       CellType::Wire(WireType::Nor(false)) => Color3::rgb(0.7, 0.4, 0.2),
       CellType::Wire(WireType::Nor(true)) => Color3::rgb(0.9, 0.3, 0.1),
       CellType::Wire(WireType::SignalEmitter) => Color3::rgb(1.0, 1.0, 0.7),
+      CellType::Wire(WireType::And) => Color3::rgb(0.4, 0.6, 0.2),
+      CellType::Wire(WireType::Or) => Color3::rgb(0.2, 0.6, 0.4),
+      CellType::Wire(WireType::Xor) => Color3::rgb(0.2, 0.4, 0.6),
+      CellType::Wire(WireType::Diode) => Color3::rgb(0.6, 0.6, 0.6),
+      CellType::Wire(WireType::Clock(..)) => Color3::rgb(0.9, 0.9, 0.4),
+      CellType::Wire(WireType::Delay(..)) => Color3::rgb(0.6, 0.3, 0.6),
       CellType::ElectronHead(_) => Color3::rgb(1.0, 1.0, 0.5),
       CellType::ElectronTail(_, _) => Color3::rgb(0.5, 0.2, 1.0),
       CellType::Eater => Color3::black(),
       CellType::Fuse(false) => Color3::rgb(0.5, 0.1, 0.0),
       CellType::Fuse(true) => Color3::rgb(0.8, 0.15, 0.0),
       CellType::Virus(lifetime) => Color3::rgb(1.0, 0.25, 0.0).blend(background_color(), lifetime.max(0) as f32 / (virus_lifetime+2) as f32),
+      CellType::Door(0) => Color3::rgb(0.4, 0.25, 0.1),
+      CellType::Door(_) => Color3::rgb(0.4, 0.25, 0.1).blend(background_color(), 0.4),
+      CellType::Valve(0) => Color3::rgb(0.35, 0.45, 0.55),
+      CellType::Valve(_) => Color3::rgb(0.2, 0.25, 0.3),
+      CellType::Sensor(SensorType::Material(_), false) => Color3::rgb(0.3, 0.3, 0.5),
+      CellType::Sensor(SensorType::FluidLevel(..), false) => Color3::rgb(0.2, 0.3, 0.6),
+      CellType::Sensor(SensorType::Fire, false) => Color3::rgb(0.5, 0.2, 0.2),
+      CellType::Sensor(SensorType::Life, false) => Color3::rgb(0.5, 0.5, 0.5),
+      CellType::Sensor(_, true) => Color3::rgb(1.0, 1.0, 0.8),
+      CellType::Burning(..) => Color3::rgb(0.9, 0.2, 0.0),
+      CellType::Root(..) => Color3::rgb(0.55, 0.45, 0.3),
+      CellType::Stem(typ, _, _) => grid.plants[&typ].stem_color,
+      CellType::Leaf(typ, _) => grid.plants[&typ].leaf_color,
+      CellType::Lamp(0) => Color3::rgb(0.45, 0.45, 0.35),
+      CellType::Lamp(_) => Color3::rgb(1.0, 0.95, 0.6),
     }
   }
 
@@ -233,14 +953,10 @@ impl Cell {
       return;
     }
 
-    // TODO: refactor this shit
-    let up = up_;
-    let down = down_;
-    let left = left_;
-    let right = right_;
-
-    let d_left = left + down;
-    let d_right = right + down;
+    // Everything that moves does so relative to the local gravity. Without gravity, the
+    // directions are only used for things like fire rising.
+    let gravity = grid.down_at(pos);
+    let (up, down, left, right, d_left, d_right) = relative_directions(gravity.unwrap_or(down_));
 
     let can_move_down = grid.in_range(pos+down) &&
       grid[pos+down].typ == CellType::Empty;
@@ -267,17 +983,58 @@ impl Cell {
           return;
         }
       },
-      CellType::Granular(GranularType::Nitro, _, _) if grid.in_range(pos+down+left) &&
-        grid.in_range(pos+up+right) => {
+      CellType::Granular(GranularType::Nitro, _, _, _) => {
         if rng.gen::<f64>() < 0.1 {
-          for x in range_inclusive(-1, 1) {
-            for y in range_inclusive(-1, 1) {
-              if x != 0 || y != 0 {
-                grid[pos+Vec2(x,y)].typ = CellType::ExplodingNitro(Vec2(x,y));
+          grid[pos].typ = CellType::Empty;
+          explode(grid, pos, nitro_energy, nitro_radius, rng);
+          return;
+        }
+      },
+      CellType::Fluid(FluidType::Lava, amount) => {
+        let neighbor = pos + random_dir(rng);
+        if grid.in_range(neighbor) {
+          let typ = grid[neighbor].typ;
+          match typ {
+            CellType::Fuse(false) => grid[neighbor].typ = CellType::Fuse(true),
+            CellType::Solid(SolidType::Ice) | CellType::Granular(GranularType::Snow, _, _, _) =>
+              grid[neighbor].typ = CellType::Fluid(FluidType::Water, 1.0),
+            // Water quenches the lava; a thick flow sets into obsidian and a thin one crumbles into stone
+            CellType::Fluid(FluidType::Water, water_amount) => {
+              grid[neighbor].typ = CellType::Fluid(FluidType::Steam, water_amount);
+              grid[pos].typ = if amount >= 0.5 {CellType::Solid(SolidType::Obsidian)}
+                else {CellType::Granular(GranularType::Stone, false, false, 0.0)};
+              return;
+            },
+            // Lava sets things alight the same way fire does
+            _ => if let Some(fuel) = typ.fuel(grid) {
+              if rng.gen::<f64>() < fuel.flammability(grid).ignition_chance {
+                grid[neighbor].typ = fuel.ignite(grid);
               }
+            },
+          }
+        }
+      },
+      CellType::Fluid(FluidType::Acid, amount) => {
+        let neighbor = pos + random_dir(rng);
+        if grid.in_range(neighbor) {
+          let resistance = grid[neighbor].typ.acid_resistance(grid);
+          if rng.gen::<f64>() < (1.0 - resistance) * acid_strength {
+            grid[neighbor].typ = CellType::Empty;
+            // The acid is used up as it dissolves things, so it doesn't spread forever
+            let amount = amount - acid_per_dissolve;
+            if amount <= min_fluid {
+              grid[pos].typ = CellType::Empty;
+              return;
             }
+            grid[pos].typ = CellType::Fluid(FluidType::Acid, amount);
+            return;
           }
-          grid[pos].typ = CellType::Empty;
+        }
+      },
+      // Heat dries mud back out into wet dirt
+      CellType::Fluid(FluidType::Mud, _) if near_heat(grid, pos) => {
+        if rng.gen::<f64>() < drying_rate {
+          grid[pos].typ = CellType::Granular(GranularType::Dirt, false, false, dried_mud_moisture);
           return;
         }
       },
@@ -299,20 +1056,76 @@ impl Cell {
               if rng.gen::<f64>() < 0.02 {
                 grid[neighbor].typ = CellType::Fluid(FluidType::Water, 1.0);
                 grid[pos].typ = CellType::Fluid(FluidType::Water, amount);
+                return;
               }
             },
             _ => (),
           }
         }
+        // Steam condenses back into water droplets once it's away from heat.
+        // With weather on it rises to form clouds instead.
+        if !grid.weather.enabled && !near_heat(grid, pos) && rng.gen::<f64>() < steam_condensation {
+          grid[pos].typ = CellType::Fluid(FluidType::Water, amount);
+          return;
+        }
       },
       _ => ()
     }
 
     match self.typ {
-      CellType::Granular(id, settled_45, settled_90) => {
+      CellType::Granular(id, settled_45, settled_90, mut moisture) => {
         let typ = grid.granular[&id];
+        // Soak up adjacent water, and dry out next to heat
+        if typ.absorbency > 0.0 {
+          for &dir in [up, down, left, right].iter() {
+            if moisture < 1.0 && grid.in_range(pos+dir) {
+              if let CellType::Fluid(FluidType::Water, amount) = grid[pos+dir].typ {
+                let absorbed = (amount*typ.absorbency).min(1.0-moisture);
+                grid[pos+dir].typ = CellType::Fluid(FluidType::Water, amount-absorbed);
+                moisture += absorbed;
+              }
+            }
+          }
+        }
+        if moisture > 0.0 && near_heat(grid, pos) {
+          moisture = (moisture-drying_rate).max(0.0);
+        }
+        if id == GranularType::Dirt && moisture >= mud_moisture {
+          grid[pos].typ = CellType::Fluid(FluidType::Mud, 1.0);
+          return;
+        }
+        grid[pos].typ = CellType::Granular(id, settled_45, settled_90, moisture);
+        if let (Some(plant), Some(down)) = (typ.sprouts, gravity) {
+          if sprout(grid, pos, down, plant, rng) {
+            return;
+          }
+        }
+
+        // Powders float in place without gravity
+        let fall_speed = typ.fall_speed * grid.gravity_strength;
+        let cells = cells_this_tick(fall_speed, rng);
+        if gravity.is_none() || cells == 0 {
+          return;
+        }
+        // Grains falling into an open drop become particles, so they speed up as they fall.
+        // Light powders drift down instead.
+        if fall_speed >= 1.0 && can_move_down && (1..freefall_depth+1).all(|i| grid.in_range(pos+down*i) && grid[pos+down*i].typ == CellType::Empty) {
+          launch(grid, pos, Vec2(down.x as f64, down.y as f64) * fall_speed);
+          return;
+        }
+        // Sink through lighter fluids, keeping the fluid's amount
+        if grid.in_range(pos+down) {
+          if let CellType::Fluid(id2, amount2) = grid[pos+down].typ {
+            if sinks_through(typ.density, grid.fluid[&id2].density, rng) {
+              grid[pos].typ = CellType::Fluid(id2, amount2);
+              grid[pos+down].typ = CellType::Granular(id, settled_45, settled_90, moisture);
+              grid.update(pos+down);
+              return;
+            }
+          }
+        }
         if can_move_down || ((can_move_d_left || can_move_d_right) && !settled_90 && rng.gen::<f64>() < typ.spread_speed) || ((can_move_left || can_move_right) && !settled_45 && (rng.gen::<f64>() < 0.2)) {
-          let new_pos = if can_move_down && (rng.gen::<f64>() < 1.0-typ.horizontal_spread ||
+          let mut new_pos = if can_move_down && (rng.gen::<f64>() < 1.0-typ.horizontal_spread ||
             (!can_move_d_left && !can_move_d_right)) {pos+down}
           else if can_move_d_left && !can_move_d_right {pos+d_left}
           else if can_move_d_right && !can_move_d_left {pos+d_right}
@@ -321,89 +1134,56 @@ impl Cell {
           } else if can_move_left && !can_move_right {pos+left}
           else if can_move_right && !can_move_left {pos+right}
           else if rng.gen::<f64>() < 0.5 {pos+left} else {pos+right};
+          if new_pos == pos+down {
+            new_pos = fall_end(grid, new_pos, down, cells);
+          }
           assert!(grid[new_pos].typ == CellType::Empty);
           grid[pos].typ = CellType::Empty;
-          grid[new_pos].typ = CellType::Granular(id, rng.gen::<f64>() < typ.granularity_45, rng.gen::<f64>() < typ.granularity_90);
-          if typ.fall_speed <= 1.0 || rng.gen::<f64>() < 2.0-typ.fall_speed {
-            grid.update(new_pos);
-          }
+          // Wet powders settle more easily, so they hold steeper slopes
+          grid[new_pos].typ = CellType::Granular(id, rng.gen::<f64>() < wet_granularity(typ.granularity_45, moisture),
+            rng.gen::<f64>() < wet_granularity(typ.granularity_90, moisture), moisture);
+          grid.update(new_pos);
         }
       },
-      CellType::WaterGenerator => {
-        if can_move_down {
+      CellType::WaterGenerator(needs_power) => {
+        if can_move_down && (!needs_power || grid.powered(pos)) {
           grid[pos+down].typ = CellType::Fluid(FluidType::Water, 1.0);
         }
       },
-      CellType::SandGenerator => {
-        if can_move_down {
-          grid[pos+down].typ = CellType::Granular(GranularType::Sand, false, false);
+      CellType::SandGenerator(needs_power) => {
+        if can_move_down && (!needs_power || grid.powered(pos)) {
+          grid[pos+down].typ = CellType::Granular(GranularType::Sand, false, false, 0.0);
         }
       },
-      CellType::Torch => {
-        if can_move_up {
+      CellType::Torch(needs_power) => {
+        if can_move_up && (!needs_power || grid.powered(pos)) {
           grid[pos+up].typ = CellType::Fire;
         }
       },
-      CellType::ExplodingNitro(dir) => {
-        if grid.in_range(pos+dir) && rng.gen::<f64>() < 0.6 {
-          grid[pos+dir].typ = CellType::ExplodingNitro(dir);
-          if rng.gen::<f64>() < 0.1 {
-            grid[pos].typ = CellType::Empty;
-          }
-        } else {
-          grid[pos].typ = CellType::Empty;
-        }
+      CellType::ExplodingNitro => {
+        grid[pos].typ = CellType::Empty;
+        explode(grid, pos, nitro_energy, nitro_radius, rng);
       }
-      CellType::Destroyer => {
-        if grid.in_range(pos+up) {
+      CellType::Destroyer(needs_power) => {
+        if grid.in_range(pos+up) && (!needs_power || grid.powered(pos)) {
           grid[pos+up].typ = CellType::Empty;
         }
       },
       CellType::Empty => {
-        let mut neighbors = 0;
-        let mut some_life = false;
-        let mut some_nonlife = false;
-        for neighbor in grid.moore(pos) {
-          if neighbor.typ != CellType::LifeTurningOn && neighbor.typ != CellType::Empty {
-            if neighbor.typ == CellType::LifeOn {
-              some_life = true;
-            } else {
-              some_nonlife = true;
-            }
-            neighbors += 1;
-          }
-        }
-        if (some_life && (neighbors == 3 || neighbors == 5 || neighbors == 6)) || (some_life && some_nonlife && neighbors >= 4 && neighbors <= 4) {
-          grid[pos].typ = CellType::LifeTurningOn;
+        if let Some(typ) = life_birth(grid, pos) {
+          grid[pos].typ = CellType::LifeTurningOn(typ);
         }
       }
-      CellType::LifeOn => {
-        let mut neighbors = 0;
-        let mut some_life = false;
-        let mut some_nonlife = false;
-        for neighbor in grid.moore(pos) {
-          if neighbor.typ != CellType::LifeTurningOn && neighbor.typ != CellType::Empty {
-            if neighbor.typ == CellType::LifeOn {
-              some_life = true;
-            } else {
-              some_nonlife = true;
-            }
-            neighbors += 1;
-          }
-        }
-        if (!some_life || (neighbors != 2 && neighbors != 3)) && (!some_life || !some_nonlife || neighbors < 4 || neighbors > 4) {
+      CellType::LifeOn(typ) => {
+        let (neighbors, some_life, some_nonlife) = life_neighbors(grid, pos, typ);
+        if !grid.life[&typ].survives(neighbors, some_life, some_nonlife) {
           grid[pos].typ = CellType::Empty;
         }
       }
       CellType::Wire(wtype) => {
-        let mut neighbors = 0;
+        let mut neighbors = incoming_signals(grid, pos, wtype);
         let mut set_nor = false;
         for neighbor in grid.moore(pos) {
-          if let CellType::ElectronHead(nbr_wtype) = neighbor.typ {
-            if nbr_wtype.can_signal_to(wtype) {
-              neighbors += 1;
-            }
-          }
           if let CellType::Wire(WireType::Nor(false)) = self.typ {
             if let CellType::Wire(WireType::Nor(true)) = neighbor.typ {
               neighbors += 1;
@@ -414,30 +1194,76 @@ impl Cell {
             }
           }
         }
-        if neighbors > 0 {
-          if set_nor {
-            grid[pos].typ = CellType::Wire(WireType::Nor(true));
+        match wtype {
+          WireType::SignalEmitter => grid[pos].typ = CellType::ElectronHead(WireType::SignalEmitter),
+          WireType::Clock(period, remaining) => if remaining <= 0 {
+            grid[pos].typ = CellType::ElectronHead(wtype);
           } else {
+            grid[pos].typ = CellType::Wire(WireType::Clock(period, remaining-1));
+          },
+          WireType::Delay(delay, remaining) if remaining > 0 => if remaining == 1 {
             grid[pos].typ = CellType::ElectronHead(wtype);
-          }
-        }
-        if wtype == WireType::SignalEmitter {
-          grid[pos].typ = CellType::ElectronHead(WireType::SignalEmitter);
+          } else {
+            grid[pos].typ = CellType::Wire(WireType::Delay(delay, remaining-1));
+          },
+          WireType::Delay(delay, _) => if neighbors > 0 {
+            if delay <= 0 {
+              grid[pos].typ = CellType::ElectronHead(wtype);
+            } else {
+              grid[pos].typ = CellType::Wire(WireType::Delay(delay, delay));
+            }
+          },
+          WireType::And => if neighbors >= 2 {
+            grid[pos].typ = CellType::ElectronHead(wtype);
+          },
+          WireType::Xor => if neighbors == 1 {
+            grid[pos].typ = CellType::ElectronHead(wtype);
+          },
+          _ => if neighbors > 0 {
+            if set_nor {
+              grid[pos].typ = CellType::Wire(WireType::Nor(true));
+            } else {
+              grid[pos].typ = CellType::ElectronHead(wtype);
+            }
+          },
         }
       }
       CellType::ElectronHead(base) => {
         grid[pos].typ = CellType::ElectronTail(base, tail_lifetime);
       }
+      CellType::Solid(SolidType::Metal) => {
+        if incoming_signals(grid, pos, WireType::Metal) > 0 {
+          grid[pos].typ = CellType::ElectronHead(WireType::Metal);
+        }
+        let heat = grid.heat(pos);
+        if heat > metal_ignition_temp && rng.gen::<f64>() < 0.01 {
+          grid[pos].typ = CellType::Fire;
+          return;
+        }
+        // Hot metal boils water and melts ice next to it
+        let neighbor = pos + random_dir(rng);
+        if grid.in_range(neighbor) {
+          match grid[neighbor].typ {
+            CellType::Fluid(FluidType::Water, amount) if heat > boiling_temp && rng.gen::<f64>() < 0.05 =>
+              grid[neighbor].typ = CellType::Fluid(FluidType::Steam, amount),
+            CellType::Solid(SolidType::Ice) if heat > 50.0 && rng.gen::<f64>() < 0.05 =>
+              grid[neighbor].typ = CellType::Fluid(FluidType::Water, 1.0),
+            _ => (),
+          }
+        }
+      },
       CellType::ElectronTail(base, lifetime) => {
         let lifetime = lifetime-1;
-        if lifetime == 0 {
+        if lifetime == 0 && base == WireType::Metal {
+          grid[pos].typ = CellType::Solid(SolidType::Metal);
+        } else if lifetime == 0 {
           grid[pos].typ = CellType::Wire(base.transition_to());
         } else {
           grid[pos].typ = CellType::ElectronTail(base, lifetime);
         }
       }
-      CellType::LifeTurningOn => {
-        grid[pos].typ = CellType::LifeOn;
+      CellType::LifeTurningOn(typ) => {
+        grid[pos].typ = CellType::LifeOn(typ);
       }
       CellType::Plant => {
         let mut neighbor = pos;
@@ -484,7 +1310,11 @@ impl Cell {
       },
       CellType::Fuse(false) => {
         for neighbor in grid.moore(pos) {
-          if neighbor.typ == CellType::Fuse(true) || neighbor.typ == CellType::Fire {
+          let burning = match neighbor.typ {
+            CellType::Fuse(true) | CellType::Fire | CellType::Burning(..) => true,
+            _ => false,
+          };
+          if burning {
             grid[pos].typ = CellType::Fuse(true);
             break;
           }
@@ -549,26 +1379,167 @@ impl Cell {
           } else {
             neighbor = neighbor + Vec2(0,-1)
           }
-          if grid.in_range(neighbor) {
-            match grid[neighbor].typ {
-              CellType::Plant => grid[neighbor].typ = CellType::Fire,
-              CellType::Fluid(FluidType::Oil, amount) => grid[neighbor].typ = CellType::Fire,
-              CellType::Fluid(FluidType::Methane, amount) => grid[neighbor].typ = CellType::Fire,
-              CellType::Fluid(FluidType::Water, amount) => grid[neighbor].typ = CellType::Fluid(FluidType::Steam, amount),
-              CellType::Solid(SolidType::Ice) => grid[neighbor].typ = CellType::Fluid(FluidType::Steam, 1.0),
-              _ => (),
+          burn_neighbor(grid, neighbor, rng);
+        }
+      },
+      CellType::Burning(fuel, time_left) => {
+        let flammability = fuel.flammability(grid);
+        if time_left <= 0 {
+          grid[pos].typ = if rng.gen::<f64>() < flammability.ash {CellType::Granular(GranularType::Ash, false, false, 0.0)}
+            else {CellType::Empty};
+          return;
+        }
+        grid[pos].typ = CellType::Burning(fuel, time_left-1);
+        // Flames and smoke rise off the burning cell
+        if can_move_up {
+          if rng.gen::<f64>() < 0.1 {
+            grid[pos+up].typ = CellType::Fire;
+          } else if rng.gen::<f64>() < flammability.smoke {
+            grid[pos+up].typ = CellType::Fluid(FluidType::Smoke, 1.0);
+          }
+        }
+        let neighbor = pos + random_dir(rng);
+        burn_neighbor(grid, neighbor, rng);
+      },
+      CellType::Door(open) => {
+        let powered = grid.powered(pos);
+        if powered {
+          grid[pos].typ = CellType::Door(power_hold_time);
+        } else if open > 0 {
+          grid[pos].typ = CellType::Door(open-1);
+        }
+        if powered || open > 0 {
+          pass_through(grid, pos, false, |typ| match typ {CellType::Door(_) => true, _ => false}, rng);
+        }
+      },
+      CellType::Valve(closed) => {
+        let powered = grid.powered(pos);
+        if powered {
+          grid[pos].typ = CellType::Valve(power_hold_time);
+        } else if closed > 0 {
+          grid[pos].typ = CellType::Valve(closed-1);
+        } else {
+          pass_through(grid, pos, true, |typ| match typ {CellType::Valve(_) => true, _ => false}, rng);
+        }
+      },
+      CellType::Sensor(sensor, _) => {
+        let active = grid.moore(pos).iter().any(|cell| sensor.is_triggered_by(cell.typ));
+        grid[pos].typ = CellType::Sensor(sensor, active);
+      },
+      CellType::Lamp(lit) => {
+        if grid.powered(pos) {
+          grid[pos].typ = CellType::Lamp(power_hold_time);
+        } else if lit > 0 {
+          grid[pos].typ = CellType::Lamp(lit-1);
+        }
+      },
+      CellType::Root(typ, water, growth) => {
+        let mut water = plant_water(grid, pos, typ, water);
+        if drink(grid, pos + random_dir(rng)) {
+          water = max_plant_water;
+        }
+        grid[pos].typ = CellType::Root(typ, water, growth);
+        if water == 0 {
+          // Dead roots rot back into the soil
+          if rng.gen::<f64>() < plant_decay {
+            grid[pos].typ = CellType::Granular(GranularType::Dirt, false, false, 0.0);
+          }
+          return;
+        }
+        let species = grid.plants[&typ];
+        if growth > 0 && water >= grow_water && rng.gen::<f64>() < species.growth_rate {
+          let rand = rng.gen::<f64>();
+          let dir = if rand < 0.5 {down} else if rand < 0.75 {d_left} else {d_right};
+          if grid.in_range(pos+dir) && is_soil(grid, grid[pos+dir].typ) {
+            grid[pos+dir].typ = CellType::Root(typ, water, growth-1);
+            grid.update(pos+dir);
+            // Roots that branch keep growing from here as well
+            if rng.gen::<f64>() >= species.branch_chance {
+              grid[pos].typ = CellType::Root(typ, water, 0);
             }
           }
         }
       },
+      CellType::Stem(typ, water, growth) => {
+        let water = plant_water(grid, pos, typ, water);
+        grid[pos].typ = CellType::Stem(typ, water, growth);
+        if water == 0 {
+          // Dead stems crumble into dirt
+          if rng.gen::<f64>() < plant_decay {
+            grid[pos].typ = CellType::Granular(GranularType::Dirt, false, false, 0.0);
+          }
+          return;
+        }
+        if growth > 0 && water >= grow_water && grid.lighting.level(pos) >= grow_light &&
+          rng.gen::<f64>() < grid.plants[&typ].growth_rate {
+          grow_stem(grid, pos, typ, water, growth, up, left, right, rng);
+        }
+      },
+      CellType::Leaf(typ, water) => {
+        let water = plant_water(grid, pos, typ, water);
+        grid[pos].typ = CellType::Leaf(typ, water);
+        let species = grid.plants[&typ];
+        if water == 0 {
+          // Dead leaves wither away
+          if rng.gen::<f64>() < plant_decay {
+            grid[pos].typ = CellType::Empty;
+          }
+        } else if gravity.is_some() && can_move_down && rng.gen::<f64>() < species.seed_chance {
+          grid[pos+down].typ = CellType::Granular(species.seed, false, false, 0.0);
+          grid.update(pos+down);
+        }
+      },
+      // Gases wander at random. The amount in each cell is that gas's partial pressure.
+      CellType::Fluid(id, amount) if grid.fluid[&id].gas => {
+        let typ = grid.fluid[&id];
+        // Gases lighter than air drift up and heavier ones sink, which layers different gases by density
+        let buoyancy = if gravity.is_some() {(air_density - typ.density) / air_density * 0.5} else {0.0};
+        let dir = if rng.gen::<f64>() < buoyancy.abs() {if buoyancy > 0.0 {up} else {down}}
+          else {random_dir(rng)};
+        if !grid.in_range(pos+dir) {
+          return;
+        }
+        match grid[pos+dir].typ {
+          // Expand into empty space, thinning out as it goes. Clouds hold together.
+          CellType::Empty => {
+            if amount > gas_min_split*2.0 && id != FluidType::Cloud {
+              grid[pos].typ = CellType::Fluid(id, amount*0.5);
+              grid[pos+dir].typ = CellType::Fluid(id, amount*0.5);
+            } else {
+              grid[pos].typ = CellType::Empty;
+              grid[pos+dir].typ = CellType::Fluid(id, amount);
+            }
+            grid.update(pos+dir);
+          },
+          // Clouds clump together until they're heavy enough to rain; see weather::update_weather
+          CellType::Fluid(id2, amount2) if id2 == id && id == FluidType::Cloud => {
+            let moved = amount.min(1.0 - amount2).max(0.0);
+            grid[pos].typ = CellType::Fluid(id, amount - moved);
+            grid[pos+dir].typ = CellType::Fluid(id, amount2 + moved);
+          },
+          // Even out the pressure with the same gas
+          CellType::Fluid(id2, amount2) if id2 == id => {
+            let average = (amount + amount2)*0.5;
+            grid[pos].typ = CellType::Fluid(id, average);
+            grid[pos+dir].typ = CellType::Fluid(id, average);
+          },
+          // Mix with other gases. Lighter gases always rise past heavier ones.
+          CellType::Fluid(id2, amount2) if grid.fluid[&id2].gas => {
+            let other = grid.fluid[&id2];
+            let stratify = (dir == up && other.density > typ.density) || (dir == down && other.density < typ.density);
+            if stratify || rng.gen::<f64>() < gas_mixing {
+              grid[pos].typ = CellType::Fluid(id2, amount2);
+              grid[pos+dir].typ = CellType::Fluid(id, amount);
+              grid.update(pos+dir);
+            }
+          },
+          _ => (),
+        }
+      },
+      // Liquids float in place without gravity
+      CellType::Fluid(_, _) if gravity.is_none() => (),
       CellType::Fluid(id, mut amount) => {
         let typ = grid.fluid[&id];
-        let up = typ.up_dir;
-        let down = typ.down_dir;
-
-        let d_left = left + down;
-        let d_right = right + down;
-
 
         let mydown = if rng.gen::<f64>() < 1.0-typ.horizontal_spread {down}
           else if rng.gen::<f64>() < 0.5 {d_left} else {d_right};
@@ -587,52 +1558,45 @@ impl Cell {
             }
             CellType::Fluid(id2, amount2) if id2 != id => {
               let other_typ = grid.fluid[&id2];
-              if other_typ.density < typ.density && rng.gen::<f64>() < (typ.density/other_typ.density).min(2.0) - 1.0 {
+              if sinks_through(typ.density, other_typ.density, rng) {
                 grid[pos].typ = CellType::Fluid(id2, amount2);
                 grid[pos+mydown].typ = CellType::Fluid(id, amount);
                 amount = 0.0;
               }
             }
+            // Floating powders get pushed up by the fluid sinking under them
+            CellType::Granular(id2, _, _, _) if mydown == down && sinks_through(typ.density, grid.granular[&id2].density, rng) => {
+              grid[pos].typ = grid[pos+mydown].typ;
+              grid[pos+mydown].typ = CellType::Fluid(id, amount);
+              grid.update(pos);
+              amount = 0.0;
+            }
             CellType::Empty => {
+              // Slow fluids hang in the air between falls
+              let cells = cells_this_tick(typ.fall_speed * grid.gravity_strength, rng);
+              if cells == 0 {
+                return;
+              }
               // TODO: sometimes the mass should be split in this case
+              let new_pos = fall_end(grid, pos+mydown, mydown, cells);
               grid[pos].typ = CellType::Empty;
-              grid[pos+mydown].typ = CellType::Fluid(id, amount);
-              if typ.fall_speed <= 1.0 || rng.gen::<f64>() < 2.0-typ.fall_speed {
-                grid.update(pos+mydown);
-              }
+              grid[new_pos].typ = CellType::Fluid(id, amount);
+              grid.update(new_pos);
               amount = 0.0;
             }
             _ => ()
           }
         }
 
-        if amount > 0.0 && (can_move_left || can_move_right) && rng.gen::<f64>() < 1.0 {
-          // TODO: add a config setting for this, spread_speed or something
-          if can_move_left && !can_move_right {
-            grid[pos].typ = CellType::Fluid(id, amount*0.25);
-            grid[pos+left].typ = CellType::Fluid(id, amount*0.75);
-            if typ.fall_speed <= 1.0 || rng.gen::<f64>() < 2.0-typ.fall_speed {
-              grid.update(pos+left);
-            }
-          } else if can_move_right && !can_move_left {
-            grid[pos].typ = CellType::Fluid(id, amount*0.25);
-            grid[pos+right].typ = CellType::Fluid(id, amount*0.75);
-            if typ.fall_speed <= 1.0 || rng.gen::<f64>() < 2.0-typ.fall_speed {
-              grid.update(pos+right);
-            }
-          } else if rng.gen::<f64>() < 0.5 {
-            grid[pos].typ = CellType::Fluid(id, amount*0.25);
-            grid[pos+left].typ = CellType::Fluid(id, amount*0.75);
-            if typ.fall_speed <= 1.0 || rng.gen::<f64>() < 2.0-typ.fall_speed {
-              grid.update(pos+left);
-            }
-          } else {
-            grid[pos].typ = CellType::Fluid(id, amount*0.25);
-            grid[pos+right].typ = CellType::Fluid(id, amount*0.75);
-            if typ.fall_speed <= 1.0 || rng.gen::<f64>() < 2.0-typ.fall_speed {
-              grid.update(pos+right);
-            }
-          }
+        // Viscous fluids spread sideways less often, and even out with their neighbours more slowly
+        if amount > 0.0 && (can_move_left || can_move_right) && rng.gen::<f64>() < 1.0-typ.viscosity {
+          let side = if can_move_left && !can_move_right {left}
+            else if can_move_right && !can_move_left {right}
+            else if rng.gen::<f64>() < 0.5 {left} else {right};
+          grid[pos].typ = CellType::Fluid(id, amount*0.25);
+          grid[pos+side].typ = CellType::Fluid(id, amount*0.75);
+          grid.update(pos+side);
+          erode(grid, pos, side, amount*0.75, rng);
           amount = amount * 0.25;
         }
 
@@ -642,10 +1606,11 @@ impl Cell {
         if amount > 0.0 && grid.in_range(pos+dir1) {
           match grid[pos+dir1].typ {
             CellType::Fluid(id2, amount2) if id2 == id => {
-              let mut flow = (amount - amount2)*0.5;
+              let mut flow = (amount - amount2)*0.5*(1.0-typ.viscosity);
               flow = flow.min(amount).max(0.0);
               grid[pos].typ = CellType::Fluid(id, amount-flow);
               grid[pos+dir1].typ = CellType::Fluid(id, amount2+flow);
+              erode(grid, pos, dir1, flow, rng);
               amount -= flow;
             },
             _ => ()
@@ -655,10 +1620,11 @@ impl Cell {
         if amount > 0.0 && grid.in_range(pos+dir2) {
           match grid[pos+dir2].typ {
             CellType::Fluid(id2, amount2) if id2 == id => {
-              let mut flow = (amount - amount2)*0.5;
+              let mut flow = (amount - amount2)*0.5*(1.0-typ.viscosity);
               flow = flow.min(amount).max(0.0);
               grid[pos].typ = CellType::Fluid(id, amount-flow);
               grid[pos+dir2].typ = CellType::Fluid(id, amount2+flow);
+              erode(grid, pos, dir2, flow, rng);
               amount -= flow;
             },
             _ => ()
@@ -682,9 +1648,7 @@ impl Cell {
               if amount - amount_in_bottom > min_fluid {
                 grid[pos].typ = CellType::Fluid(id, amount_in_bottom);
                 grid[pos+up].typ = CellType::Fluid(id, amount - amount_in_bottom);
-                if typ.fall_speed <= 1.0 || rng.gen::<f64>() < 2.0-typ.fall_speed {
-                  grid.update(pos+up);
-                }
+                grid.update(pos+up);
               }
             }
             _ => ()
@@ -698,23 +1662,444 @@ impl Cell {
 }
 
 
+/// Lets liquid flowing sideways from `pos` pick up the powder under it and carry it downstream.
+/// The grain is swapped with the liquid it flowed into, so it's lifted into the stream; from there
+/// it's either picked up again by the flow above it or, where the flow slows, sinks and is deposited.
+fn erode<R: Rng>(grid: &mut Grid, pos: Vec2<i32>, dir: Vec2<i32>, flow: f64, rng: &mut R) {
+  let bed = match grid.down_at(pos) {
+    Some(down) => pos + down,
+    None => return,
+  };
+  let downstream = pos + dir;
+  if !grid.in_range(bed) || !grid.in_range(downstream) {
+    return;
+  }
+  let liquid = match grid[pos].typ {
+    CellType::Fluid(id, _) => !grid.fluid[&id].gas,
+    _ => false,
+  };
+  if let (true, CellType::Granular(id, _, _, _), CellType::Fluid(..)) = (liquid, grid[bed].typ, grid[downstream].typ) {
+    if rng.gen::<f64>() < flow * grid.granular[&id].erodibility * erosion_rate {
+      let grain = grid[bed].typ;
+      grid[bed].typ = grid[downstream].typ;
+      grid[downstream].typ = grain;
+      grid.update(downstream);
+    }
+  }
+}
+
+/// How many cells something with the given speed moves this tick.
+/// The whole part of the speed is always moved, and the fraction is the chance of moving one more,
+/// so slow materials only move on some ticks.
+fn cells_this_tick<R: Rng>(speed: f64, rng: &mut R) -> i32 {
+  speed.floor() as i32 + if rng.gen::<f64>() < speed.fract() {1} else {0}
+}
+
+/// Where something that's just moved to `pos` ends up if it keeps falling in `dir` for up to
+/// `cells` cells in total, through empty cells only
+fn fall_end(grid: &Grid, pos: Vec2<i32>, dir: Vec2<i32>, cells: i32) -> Vec2<i32> {
+  let mut end = pos;
+  for _ in 1..cells {
+    if !grid.in_range(end+dir) || grid[end+dir].typ != CellType::Empty {
+      break;
+    }
+    end = end + dir;
+  }
+  end
+}
+
+/// The chance of a powder settling, given its chance when dry
+fn wet_granularity(granularity: f64, moisture: f64) -> f64 {
+  granularity + (1.0-granularity)*moisture*wet_cohesion
+}
+
+/// Whether anything next to a cell is hot enough to dry it out
+fn near_heat(grid: &Grid, pos: Vec2<i32>) -> bool {
+  for y in -1..2 {
+    for x in -1..2 {
+      let neighbor = pos + Vec2(x, y);
+      if (x != 0 || y != 0) && grid.in_range(neighbor) &&
+        (grid[neighbor].typ.heat_source().is_some() || grid.heat(neighbor) >= boiling_temp) {
+        return true;
+      }
+    }
+  }
+  false
+}
+
+/// Whether something sinks through something lighter this tick. The bigger the difference, the faster it sinks.
+fn sinks_through<R: Rng>(density: f64, other_density: f64, rng: &mut R) -> bool {
+  other_density < density && rng.gen::<f64>() < (density/other_density).min(2.0) - 1.0
+}
+
+/// Spreads fire to a cell: it sets flammable cells alight, and boils water and ice
+fn burn_neighbor<R: Rng>(grid: &mut Grid, neighbor: Vec2<i32>, rng: &mut R) {
+  if !grid.in_range(neighbor) {
+    return;
+  }
+  let typ = grid[neighbor].typ;
+  match typ {
+    CellType::Fluid(FluidType::Water, amount) => grid[neighbor].typ = CellType::Fluid(FluidType::Steam, amount),
+    CellType::Solid(SolidType::Ice) => grid[neighbor].typ = CellType::Fluid(FluidType::Steam, 1.0),
+    CellType::Granular(GranularType::Nitro, _, _, _) => grid[neighbor].typ = CellType::ExplodingNitro,
+    _ => if let Some(fuel) = typ.fuel(grid) {
+      let flammability = fuel.flammability(grid);
+      if rng.gen::<f64>() < flammability.ignition_chance {
+        grid[neighbor].typ = fuel.ignite(grid);
+      }
+    },
+  }
+}
+
+/// Sprouts a seed that's resting on wet enough dirt. The seed becomes the bottom of the stem,
+/// and the dirt under it the top of the roots. Returns whether it sprouted.
+fn sprout<R: Rng>(grid: &mut Grid, pos: Vec2<i32>, down: Vec2<i32>, typ: PlantType, rng: &mut R) -> bool {
+  if !grid.in_range(pos+down) {
+    return false;
+  }
+  match grid[pos+down].typ {
+    CellType::Granular(GranularType::Dirt, _, _, moisture) if moisture >= sprout_moisture && rng.gen::<f64>() < sprout_chance => {
+      let species = grid.plants[&typ];
+      grid[pos].typ = CellType::Stem(typ, max_plant_water, species.height);
+      grid[pos+down].typ = CellType::Root(typ, max_plant_water, species.root_depth);
+      grid.update(pos+down);
+      true
+    },
+    _ => false,
+  }
+}
+
+/// How much water a plant part has left after a tick. Water passes from each part to the parts of
+/// the same plant next to it, losing one each time, so the parts furthest from the roots dry out first.
+fn plant_water(grid: &Grid, pos: Vec2<i32>, typ: PlantType, water: i32) -> i32 {
+  let mut water = water;
+  for neighbor in grid.moore(pos) {
+    match neighbor.typ {
+      CellType::Root(typ2, water2, _) | CellType::Stem(typ2, water2, _) | CellType::Leaf(typ2, water2) if typ2 == typ =>
+        water = cmp::max(water, water2),
+      _ => (),
+    }
+  }
+  cmp::max(water-1, 0)
+}
+
+/// Lets a root drink from the cell next to it, which can be water or wet powder.
+/// Returns whether there was anything to drink.
+fn drink(grid: &mut Grid, pos: Vec2<i32>) -> bool {
+  if !grid.in_range(pos) {
+    return false;
+  }
+  match grid[pos].typ {
+    CellType::Fluid(FluidType::Water, amount) => {
+      grid[pos].typ = CellType::Fluid(FluidType::Water, (amount-root_drink).max(0.0));
+      true
+    },
+    CellType::Granular(id, settled_45, settled_90, moisture) if moisture > 0.0 => {
+      grid[pos].typ = CellType::Granular(id, settled_45, settled_90, (moisture-root_drink).max(0.0));
+      true
+    },
+    _ => false,
+  }
+}
+
+/// Whether roots can grow into a cell: any powder that holds water, apart from seeds
+fn is_soil(grid: &Grid, typ: CellType) -> bool {
+  match typ {
+    CellType::Granular(id, _, _, _) => grid.granular[&id].absorbency > 0.0 && grid.granular[&id].sprouts.is_none(),
+    _ => false,
+  }
+}
+
+/// Grows the tip of a stem by one cell, towards whichever of up and the two upward diagonals is
+/// least crowded, so branches spread apart. Along the way the stem branches off and puts out leaves
+/// at random, and it ends in a crown of leaves.
+fn grow_stem<R: Rng>(grid: &mut Grid, pos: Vec2<i32>, typ: PlantType, water: i32, growth: i32,
+  up: Vec2<i32>, left: Vec2<i32>, right: Vec2<i32>, rng: &mut R) {
+  let species = grid.plants[&typ];
+  let is_open = |grid: &Grid, pos: Vec2<i32>| grid.in_range(pos) && grid[pos].typ == CellType::Empty;
+
+  // Straight up wins ties
+  let diagonals = if rng.gen::<f64>() < 0.5 {(up+left, up+right)} else {(up+right, up+left)};
+  let mut tip = None;
+  let mut least_crowded = 9;
+  for &dir in [up, diagonals.0, diagonals.1].iter() {
+    if !is_open(grid, pos+dir) {
+      continue;
+    }
+    let crowded = grid.moore(pos+dir).iter().filter(|cell| cell.typ != CellType::Empty).count();
+    if crowded < least_crowded {
+      tip = Some(pos+dir);
+      least_crowded = crowded;
+    }
+  }
+  let tip = match tip {
+    Some(tip) => tip,
+    None => return,
+  };
+  grid[tip].typ = CellType::Stem(typ, water, growth-1);
+  grid.update(tip);
+  grid[pos].typ = CellType::Stem(typ, water, 0);
+
+  let side = if rng.gen::<f64>() < 0.5 {left} else {right};
+  if is_open(grid, pos+side) {
+    // Side branches don't grow as far as the stem they come off
+    if growth > 2 && rng.gen::<f64>() < species.branch_chance {
+      grid[pos+side].typ = CellType::Stem(typ, water, growth/2);
+      grid.update(pos+side);
+    } else if rng.gen::<f64>() < species.leaf_chance {
+      grid[pos+side].typ = CellType::Leaf(typ, water);
+      grid.update(pos+side);
+    }
+  }
+
+  if growth == 1 {
+    for &dir in [up, left, right, up+left, up+right].iter() {
+      if is_open(grid, tip+dir) {
+        grid[tip+dir].typ = CellType::Leaf(typ, water);
+        grid.update(tip+dir);
+      }
+    }
+  }
+}
+
+/// The number of adjacent electron heads (and active sensors) that can signal to a wire of the given type
+fn incoming_signals(grid: &Grid, pos: Vec2<i32>, wtype: WireType) -> i32 {
+  let mut signals = 0;
+  for neighbor in grid.moore(pos) {
+    if let CellType::ElectronHead(nbr_wtype) = neighbor.typ {
+      if nbr_wtype.can_signal_to(wtype) {
+        signals += 1;
+      }
+    }
+    // Active sensors signal like the head of a normal wire
+    if let CellType::Sensor(_, true) = neighbor.typ {
+      if WireType::Normal.can_signal_to(wtype) {
+        signals += 1;
+      }
+    }
+  }
+  signals
+}
+
+fn random_dir<R: Rng>(rng: &mut R) -> Vec2<i32> {
+  let rand = rng.gen::<f64>();
+  if rand < 0.25 {
+    Vec2(1,0)
+  } else if rand < 0.5 {
+    Vec2(-1,0)
+  } else if rand < 0.75 {
+    Vec2(0,1)
+  } else {
+    Vec2(0,-1)
+  }
+}
+
+/// Lets material pass through a gap made of cells matching `is_gap`, such as an open door.
+/// Material falls (or rises, for gases) through it, and fluids can also flow through sideways.
+fn pass_through<R: Rng, F: Fn(CellType) -> bool>(grid: &mut Grid, pos: Vec2<i32>, fluids_only: bool, is_gap: F, rng: &mut R) {
+  let sideways = if rng.gen::<f64>() < 0.5 {(left_, right_)} else {(right_, left_)};
+  for &(from_dir, to_dir) in [(up_, down_), (down_, up_), sideways].iter() {
+    let (from, to) = match (gap_end(grid, pos, from_dir, &is_gap), gap_end(grid, pos, to_dir, &is_gap)) {
+      (Some(from), Some(to)) => (from, to),
+      _ => continue,
+    };
+    if grid[to].typ != CellType::Empty || grid.updated(from) {
+      continue;
+    }
+    // How far the move goes along gravity; positive is downhill
+    let fall = match grid.down_at(pos) {
+      Some(down) => to_dir.x*down.x + to_dir.y*down.y,
+      None => 0,
+    };
+    let can_pass = match grid[from].typ {
+      CellType::Granular(..) => !fluids_only && fall > 0,
+      // Gases go anywhere, and liquids go down or sideways
+      CellType::Fluid(id, _) => grid.fluid[&id].gas || fall >= 0,
+      _ => false,
+    };
+    if can_pass {
+      grid[to].typ = grid[from].typ;
+      grid[from].typ = CellType::Empty;
+      grid.update(to);
+    }
+  }
+}
+
+/// The first cell past a gap in the given direction
+fn gap_end<F: Fn(CellType) -> bool>(grid: &Grid, pos: Vec2<i32>, dir: Vec2<i32>, is_gap: &F) -> Option<Vec2<i32>> {
+  let mut pos = pos + dir;
+  // The limit stops this from looping forever when the gap wraps around the world
+  for _ in 0..cmp::max(grid.size.x, grid.size.y) {
+    if !grid.in_range(pos) {
+      return None;
+    }
+    if !is_gap(grid[pos].typ) {
+      return Some(pos);
+    }
+    pos = pos + dir;
+  }
+  None
+}
+
 // TODO: get rid of this hack
 const up_: Vec2<i32> = Vec2{x: 0, y: -1};
 const down_: Vec2<i32> = Vec2{x: 0, y: 1};
 const left_: Vec2<i32> = Vec2{x: -1, y: 0};
 const right_: Vec2<i32> = Vec2{x: 1, y: 0};
 
+// The eight neighbours, going clockwise from the right
+const directions: [Vec2<i32>; 8] = [Vec2{x: 1, y: 0}, Vec2{x: 1, y: 1}, Vec2{x: 0, y: 1}, Vec2{x: -1, y: 1},
+  Vec2{x: -1, y: 0}, Vec2{x: -1, y: -1}, Vec2{x: 0, y: -1}, Vec2{x: 1, y: -1}];
+
+/// Up, down, left, right, down-left and down-right for the given down direction, which can be
+/// any of the eight neighbours. For diagonal gravity the diagonals are the cells either side of down.
+pub fn relative_directions(down: Vec2<i32>) -> (Vec2<i32>, Vec2<i32>, Vec2<i32>, Vec2<i32>, Vec2<i32>, Vec2<i32>) {
+  let index = directions.iter().position(|&dir| dir == down).expect("Gravity must point at a neighbouring cell") as i32;
+  let dir = |offset: i32| directions[wrap(index+offset, 8) as usize];
+  (dir(4), dir(0), dir(2), dir(-2), dir(1), dir(-1))
+}
+
 
 
+/// Debug views that replace the normal palette with a heatmap of some per-cell value
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Overlay {
+  None,
+  FluidAmount,
+  Updated,
+  Settled,
+  Electrical,
+  /// Cells that a traced signal could reach, brighter for cells it reaches sooner
+  SignalReach,
+  Heat,
+  Light,
+}
+
+impl Overlay {
+  pub fn name(self) -> &'static str {
+    match self {
+      Overlay::None => "normal",
+      Overlay::FluidAmount => "fluid amount",
+      Overlay::Updated => "updated",
+      Overlay::Settled => "settled",
+      Overlay::Electrical => "electrical",
+      Overlay::SignalReach => "signal reach",
+      Overlay::Heat => "heat",
+      Overlay::Light => "light",
+    }
+  }
+
+  pub fn next(self) -> Overlay {
+    match self {
+      Overlay::None => Overlay::FluidAmount,
+      Overlay::FluidAmount => Overlay::Updated,
+      Overlay::Updated => Overlay::Settled,
+      Overlay::Settled => Overlay::Electrical,
+      Overlay::Electrical => Overlay::SignalReach,
+      Overlay::SignalReach => Overlay::Heat,
+      Overlay::Heat => Overlay::Light,
+      Overlay::Light => Overlay::None,
+    }
+  }
+
+  /// The heatmap value for a cell, from 0.0-1.0, or None if the cell isn't highlighted
+  pub fn value(self, grid: &Grid, trace: Option<&SignalTrace>, pos: Vec2<i32>) -> Option<f32> {
+    match (self, grid[pos].typ) {
+      (Overlay::None, _) => None,
+      // Compressed fluid can hold a bit more than 1.0, so leave some headroom
+      (Overlay::FluidAmount, CellType::Fluid(_, amount)) => Some((amount as f32 * 0.5).min(1.0)),
+      (Overlay::FluidAmount, _) => None,
+      (Overlay::Updated, _) => if grid.updated(pos) {Some(1.0)} else {None},
+      (Overlay::Settled, CellType::Granular(_, settled_45, settled_90, _)) => Some(match (settled_45, settled_90) {
+        (false, false) => 0.0,
+        (true, false) => 0.33,
+        (false, true) => 0.67,
+        (true, true) => 1.0,
+      }),
+      (Overlay::Settled, _) => None,
+      (Overlay::Electrical, CellType::ElectronHead(_)) => Some(1.0),
+      (Overlay::Electrical, CellType::Wire(WireType::Nor(true))) => Some(0.67),
+      (Overlay::Electrical, CellType::ElectronTail(_, _)) => Some(0.33),
+      (Overlay::Electrical, _) => None,
+      (Overlay::Heat, _) => {
+        let heat = grid.heat(pos);
+        if heat > ambient_temp + 1.0 {Some(((heat - ambient_temp) / 1000.0).min(1.0) as f32)} else {None}
+      },
+      (Overlay::Light, _) => Some(grid.lighting.level(pos) as f32),
+      (Overlay::SignalReach, _) => trace.and_then(|trace| trace.ticks_to_reach(pos)
+        .map(|ticks| 1.0 - ticks as f32 / (trace.max_ticks+1) as f32)),
+    }
+  }
+}
+
+/// The cells that a signal starting at a given cell could reach within a number of ticks.
+/// This only follows `can_signal_to`, so it ignores gate conditions and Nor states,
+/// but it does take delay lines into account.
+pub struct SignalTrace {
+  pub origin: Vec2<i32>,
+  pub max_ticks: i32,
+  ticks: Vec<Vec<Option<i32>>>,
+}
+
+impl SignalTrace {
+  pub fn new(grid: &Grid, origin: Vec2<i32>, max_ticks: i32) -> SignalTrace {
+    let mut ticks: Vec<Vec<Option<i32>>> = (0..grid.size.y).map(|_| repeat(None).take(grid.size.x as usize).collect()).collect();
+    let mut queue = VecDeque::new();
+    if grid[origin].typ.wire_type().is_some() {
+      ticks[origin.y as usize][origin.x as usize] = Some(0);
+      queue.push_back(origin);
+    }
+    while let Some(pos) = queue.pop_front() {
+      let wtype = grid[pos].typ.wire_type().unwrap();
+      let cur_ticks = ticks[pos.y as usize][pos.x as usize].unwrap();
+      for &dir in directions.iter() {
+        // Signals cross wrapping edges, so the trace does too
+        let next = match grid.resolve(pos+dir) {
+          Some(next) => next,
+          None => continue,
+        };
+        let next_wtype = match grid[next].typ.wire_type() {
+          Some(next_wtype) if wtype.can_signal_to(next_wtype) => next_wtype,
+          _ => continue,
+        };
+        let next_ticks = cur_ticks + match next_wtype {
+          WireType::Delay(delay, _) => 1 + delay.max(0),
+          _ => 1,
+        };
+        let old_ticks = ticks[next.y as usize][next.x as usize];
+        if next_ticks <= max_ticks && old_ticks.map_or(true, |old_ticks| next_ticks < old_ticks) {
+          ticks[next.y as usize][next.x as usize] = Some(next_ticks);
+          queue.push_back(next);
+        }
+      }
+    }
+    SignalTrace{origin: origin, max_ticks: max_ticks, ticks: ticks}
+  }
+
+  pub fn ticks_to_reach(&self, pos: Vec2<i32>) -> Option<i32> {
+    self.ticks[pos.y as usize][pos.x as usize]
+  }
+}
+
 pub struct World {
   id: Id,
   mesh: glium::VertexBuffer<UnlitVertex>,//NewMesh<UnlitProgram>,
   texture: texture::Texture2d,//Texture,
+  // Maps material IDs to colours; see CellType::material_id
+  palette: texture::Texture1d,
   coords: Vec<Vec2<i32>>,
   pub grid: Grid,
-  // pixels: Vec<Vec<(u8,u8,u8)>>, //Vec<u8>,
-  pixels: Vec<(u8,u8,u8)>,
+  // The material ID, shade, light level and brightness of each cell
+  pixels: Vec<(u8,u8,u8,u8)>,
   unlit_program: glium::Program,
+  pub overlay: Overlay,
+  // When set, only wires and electrons are simulated
+  pub electrical_only: bool,
+  // The cell and number of ticks used for the SignalReach overlay
+  pub trace_origin: Option<Vec2<i32>>,
+  pub trace_ticks: i32,
+  // Output wires that pause the simulation when they become electron heads
+  pub breakpoints: Vec<Vec2<i32>>,
 }
 
 #[derive(Copy, Clone)]
@@ -726,14 +2111,6 @@ implement_vertex!(UnlitVertex, pos, texcoord);
 
 impl World {
   pub fn new<R: Rng>(size: Vec2<i32>, window: &Window, rng: &mut R) -> World {
-    let mut cells = Vec::new();
-    let mut updated = Vec::new();
-    for y in 0..size.y {
-      let row = repeat(Cell{typ: CellType::Empty}).take(size.x as usize).collect();
-      let updated_row = repeat(false).take(size.x as usize).collect();
-      cells.push(row);
-      updated.push(updated_row);
-    }
     let mesh = glium::VertexBuffer::new(window, &vec![
       UnlitVertex{pos: Vec2(0.0, 0.0),
         texcoord: Vec2(0.0, 0.0)},
@@ -771,11 +2148,10 @@ impl World {
     mesh.add_triangle(0, 1, 2);
     mesh.add_triangle(2, 3, 0);*/
 
-    /*let texture = Texture::texture2d_empty(size.x*cell_size, size.y*cell_size, gl::RGB8,
-      MinNearest, MagNearest);*/
-    let texture = texture::Texture2d::empty_with_format(window, texture::UncompressedFloatFormat::U8U8U8, MipmapsOption::NoMipmap, (size.x*cell_size) as u32, (size.y*cell_size) as u32).unwrap();
+    // The texture holds one texel per cell; the fragment shader looks up its colour in the palette
+    let texture = texture::Texture2d::empty_with_format(window, texture::UncompressedFloatFormat::U8U8U8U8, MipmapsOption::NoMipmap, size.x as u32, size.y as u32).unwrap();
 
-    let pixels = Vec::new();//Vec::with_capacity((size.x*size.y*3) as usize);
+    let pixels = Vec::with_capacity((size.x*size.y) as usize);
 
     // We use a pre-shuffled list of coordinates to get rid of some poblems
     // in the simulation. Without it, some materials would prefer to move
@@ -790,16 +2166,300 @@ impl World {
     }
     rng.shuffle(&mut coords);
 
+    let grid = Grid::new(size);
+
+    let mut palette_colors = repeat((0, 0, 0)).take(256).collect::<Vec<(u8,u8,u8)>>();
+    for typ in materials() {
+      let color = Cell{typ: typ}.color(&grid);
+      palette_colors[typ.material_id() as usize] = ((color.r*255.0) as u8, (color.g*255.0) as u8, (color.b*255.0) as u8);
+    }
+    let palette = texture::Texture1d::new(window, palette_colors).unwrap();
+
+    World{grid: grid, mesh: mesh, texture: texture, palette: palette, coords: coords, pixels: pixels, id: Id::new(), unlit_program: unlit_program,
+      overlay: Overlay::None, electrical_only: false, trace_origin: None, trace_ticks: 20, breakpoints: Vec::new()}
+  }
+
+  /// Runs one tick of the simulation. Returns the breakpoint that was hit, if any.
+  pub fn simulate<R: Rng>(&mut self, rng: &mut R) -> Option<Vec2<i32>> {
+    if !self.electrical_only {
+      self.grid.inflow(rng);
+      self.grid.conduct_heat();
+      self.grid.equalize_pressure(rng);
+      if self.grid.weather.enabled {
+        update_weather(&mut self.grid, rng);
+      }
+    }
+
+    for y in 0..self.grid.size.y {
+      for x in 0..self.grid.size.x {
+        match self.grid.cells[y as usize][x as usize].typ {
+          CellType::Fluid(id, amount) if amount <= min_fluid => {
+            assert!(amount >= 0.0);
+            self.grid.cells[y as usize][x as usize].typ = CellType::Empty;
+          },
+          // Smoke slowly thins out until it's cleaned up like any other almost-dry fluid
+          CellType::Fluid(FluidType::Smoke, amount) if !self.electrical_only => {
+            self.grid.cells[y as usize][x as usize].typ = CellType::Fluid(FluidType::Smoke, (amount - smoke_fade).max(0.0));
+          },
+          _ => ()
+        }
+
+        self.grid.updated[y as usize][x as usize] = false;
+      }
+    }
+
+    for &coord in self.coords.iter() {
+      let cell = self.grid.cells[coord.y as usize][coord.x as usize];
+      if !self.electrical_only {
+        cell.simulate(&mut self.grid, coord, rng);
+      } else if cell.typ == CellType::Solid(SolidType::Metal) {
+        // Metal still conducts in circuit mode, but its heat is paused along with everything else
+        if incoming_signals(&self.grid, coord, WireType::Metal) > 0 {
+          self.grid[coord].typ = CellType::ElectronHead(WireType::Metal);
+        }
+      } else if cell.typ.wire_type().is_some() {
+        cell.simulate(&mut self.grid, coord, rng);
+      }
+    }
+    if !self.electrical_only {
+      move_particles(&mut self.grid);
+    }
+    if self.grid.lighting.enabled {
+      update_light(&mut self.grid);
+    }
+
+    self.breakpoints.iter().cloned().find(|&pos| self.grid[pos].typ == CellType::ElectronHead(WireType::Output))
+  }
+
+  /// Adds a breakpoint on an output wire, or removes it if there's already one there.
+  /// Returns false if the cell isn't an output wire.
+  pub fn toggle_breakpoint(&mut self, pos: Vec2<i32>) -> bool {
+    if let Some(i) = self.breakpoints.iter().position(|&x| x == pos) {
+      self.breakpoints.remove(i);
+      return true;
+    }
+    if self.grid[pos].typ.wire_type() != Some(WireType::Output) {
+      return false;
+    }
+    self.breakpoints.push(pos);
+    true
+  }
+
+  pub fn update_mesh(&mut self, window: &Window) {
+    self.pixels.clear();
+    let trace = match (self.overlay, self.trace_origin) {
+      (Overlay::SignalReach, Some(origin)) => Some(SignalTrace::new(&self.grid, origin, self.trace_ticks)),
+      _ => None,
+    };
+    for y in 0..self.grid.size.y as usize {
+      for x in 0..self.grid.size.x as usize {
+        let typ = self.grid.cells[y][x].typ;
+        // In overlay modes the shade is replaced by the heatmap value; 0 means "not highlighted"
+        let payload = match self.overlay {
+          Overlay::None => (typ.shade()*255.0) as u8,
+          overlay => match overlay.value(&self.grid, trace.as_ref(), Vec2(x as i32, y as i32)) {
+            Some(value) => 1 + (value*254.0) as u8,
+            None => 0,
+          },
+        };
+        let light = (self.grid.lighting.level(Vec2(x as i32, y as i32))*255.0) as u8;
+        self.pixels.push((typ.material_id(), payload, light, (typ.brightness()*255.0) as u8));
+      }
+    }
+    for particle in self.grid.particles.iter() {
+      if let Some(pos) = self.grid.resolve(particle.cell()) {
+        let payload = if self.overlay == Overlay::None {(particle.typ.shade()*255.0) as u8} else {0};
+        let light = (self.grid.lighting.level(pos)*255.0) as u8;
+        self.pixels[(pos.y*self.grid.size.x + pos.x) as usize] = (particle.typ.material_id(), payload, light, (particle.typ.brightness()*255.0) as u8);
+      }
+    }
+
+    let raw = RawImage2d{
+      data: Cow::Borrowed(&self.pixels),
+      width: self.grid.size.x as u32,
+      height: self.grid.size.y as u32,
+      format: ClientFormat::U8U8U8U8
+    };
+    self.texture.write(glium::Rect{left: 0, width: self.grid.size.x as u32,
+      bottom: 0, height: self.grid.size.y as u32}, raw);
+  }
+}
+
+impl Widget for World {
+  fn id(&self) -> &Id {&self.id}
+  fn draw(&mut self, pos: Vec2<i32>, size: Vec2<i32>, facade: &DrawContext, frame: &mut DrawFrame) {//window: &mut Window) {
+
+    let indices = index::NoIndices(index::PrimitiveType::TriangleFan);
+    let uniforms = uniform!{
+      modelViewMatrix: Mat4::generic_ortho(
+      Vec2::zero(), Vec2(self.grid.size.x as f32, self.grid.size.y as f32),
+      Vec2::<f32>::gen_from(pos), Vec2::<f32>::gen_from(pos+size)),
+      projMatrix: Mat4::ortho_flip(frame.width() as f32, frame.height() as f32), // TODO //window.window_size.x as f32, window.window_size.y as f32),
+      tex: Sampler::new(&self.texture)
+        .magnify_filter(MagnifySamplerFilter::Nearest)
+        .minify_filter(MinifySamplerFilter::Nearest),
+      palette: Sampler::new(&self.palette)
+        .magnify_filter(MagnifySamplerFilter::Nearest)
+        .minify_filter(MinifySamplerFilter::Nearest),
+      background: [background_color().r, background_color().g, background_color().b],
+      overlay: if self.overlay == Overlay::None {0} else {1},
+    };
+    // println!("Drawing world; {} {}", frame.width(), frame.height());
+    frame.draw(&self.mesh, &indices, &self.unlit_program, &uniforms, &default_draw_params/*, None*/);
+
+    /*self.mesh.draw(UnlitUniforms{
+      model_view_matrix: Mat4::generic_ortho(
+      Vec2::zero(), Vec2(self.grid.size.x as f32, self.grid.size.y as f32),
+      Vec2::<f32>::gen_from(pos), Vec2::<f32>::gen_from(pos+size)),
+      proj_matrix: Mat4::ortho_flip(window.window_size.x as f32, window.window_size.y as f32),
+      tex: &self.texture,
+    });*/
+  }
+
+  fn min_size(&self, facade: &DrawContext) -> Vec2<i32> {
+    self.grid.size * cell_size
+  }
+}
+
+
+
+/// Which way things fall
+#[derive(Copy, Clone, PartialEq)]
+pub enum Gravity {
+  /// The same everywhere. The direction can be any of the eight neighbours, including diagonals.
+  Uniform(Vec2<i32>),
+  /// Towards a cell, like a planet
+  Point(Vec2<i32>),
+  /// Nothing falls
+  Zero,
+}
+
+/// What happens to cells at one edge of the world
+#[derive(Copy, Clone, PartialEq)]
+pub enum Boundary {
+  /// The edge acts as a wall
+  Solid,
+  /// Cells that move past the edge are deleted
+  Void,
+  /// Cells that move past the edge reappear at the opposite edge.
+  /// This should be set on both opposite edges.
+  Wrap,
+  /// The edge acts as a wall, but each empty cell along it is filled with the given
+  /// cell type with the given probability per tick
+  Inflow(CellType, f64),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Boundaries {
+  pub top: Boundary,
+  pub bottom: Boundary,
+  pub left: Boundary,
+  pub right: Boundary,
+}
+
+impl Boundaries {
+  pub fn all(boundary: Boundary) -> Boundaries {
+    Boundaries{top: boundary, bottom: boundary, left: boundary, right: boundary}
+  }
+
+  /// The same boundaries, with every inflow edge feeding in the given cell type at the given rate
+  pub fn with_inflow(self, typ: CellType, rate: f64) -> Boundaries {
+    let inflow = |boundary| match boundary {
+      Boundary::Inflow(..) => Boundary::Inflow(typ, rate),
+      _ => boundary,
+    };
+    Boundaries{top: inflow(self.top), bottom: inflow(self.bottom), left: inflow(self.left), right: inflow(self.right)}
+  }
+}
+
+enum Location {
+  Inside(Vec2<i32>),
+  Void,
+  Outside,
+}
+
+static empty_cell: Cell = Cell{typ: CellType::Empty};
+
+pub struct Grid {
+  pub size: Vec2<i32>,
+  pub boundaries: Boundaries,
+  cells: Vec<Vec<Cell>>,
+  updated: Vec<Vec<bool>>,
+  // The temperature at each position. Only conductors hold heat; everything else is at the ambient temperature.
+  heat: Vec<Vec<f64>>,
+  // Writes past a void edge go here and are never read back
+  void_cell: Cell,
+  solid: HashMap<SolidType, Solid>,
+  granular: HashMap<GranularType, Granular>,
+  fluid: HashMap<FluidType, Fluid>,
+  plants: HashMap<PlantType, PlantSpecies>,
+  life: HashMap<LifeType, Life>,
+  // Cells flying freely over the grid; see particle::move_particles
+  pub particles: Vec<Particle>,
+  pub weather: Weather,
+  pub lighting: Lighting,
+  pub gravity: Gravity,
+  // Scales how fast things fall
+  pub gravity_strength: f64,
+}
+
+impl Grid {
+  /// An empty grid with the standard materials
+  pub fn new(size: Vec2<i32>) -> Grid {
+    let mut cells = Vec::new();
+    let mut updated = Vec::new();
+    for y in 0..size.y {
+      let row = repeat(Cell{typ: CellType::Empty}).take(size.x as usize).collect();
+      let updated_row = repeat(false).take(size.x as usize).collect();
+      cells.push(row);
+      updated.push(updated_row);
+    }
+
     let solid = vec![
       Solid{
         typ: SolidType::Wall,
         name: "wall",
         color: Color3::rgb(0.5, 0.5, 0.5),
+        heat_conductivity: 0.0,
+        transparency: 0.0,
+        acid_resistance: 0.95,
+        hardness: 4.0,
       },
       Solid{
         typ: SolidType::Ice,
         name: "ice",
         color: Color3::white().blend(background_color(), 0.65),
+        heat_conductivity: 0.0,
+        transparency: 0.9,
+        acid_resistance: 0.5,
+        hardness: 1.0,
+      },
+      Solid{
+        typ: SolidType::Metal,
+        name: "metal",
+        color: Color3::rgb(0.6, 0.65, 0.7),
+        heat_conductivity: 0.2,
+        transparency: 0.0,
+        acid_resistance: 0.8,
+        hardness: 6.0,
+      },
+      Solid{
+        typ: SolidType::Glass,
+        name: "glass",
+        color: Color3::rgb(0.8, 0.9, 0.95).blend(background_color(), 0.4),
+        heat_conductivity: 0.0,
+        transparency: 0.95,
+        acid_resistance: 1.0,
+        hardness: 0.5,
+      },
+      Solid{
+        typ: SolidType::Obsidian,
+        name: "obsidian",
+        color: Color3::rgb(0.15, 0.1, 0.2),
+        heat_conductivity: 0.0,
+        transparency: 0.0,
+        acid_resistance: 0.97,
+        hardness: 8.0,
       },
     ];
     // TODO: move these to a config file
@@ -813,6 +2473,13 @@ impl World {
         spread_speed: 0.8,
         fall_speed: 1.0,
         color: Color3::yellow()*0.9,
+        density: 1.6,
+        erodibility: 0.3,
+        absorbency: 0.05,
+        sprouts: None,
+        acid_resistance: 0.7,
+        flammability: None,
+        hardness: 0.3,
       },
       Granular{
         typ: GranularType::Dirt,
@@ -823,6 +2490,13 @@ impl World {
         spread_speed: 0.8,
         fall_speed: 1.0,
         color: Color3::rgb(0.3, 0.13, 0.0),
+        density: 1.3,
+        erodibility: 0.5,
+        absorbency: 0.1,
+        sprouts: None,
+        acid_resistance: 0.1,
+        flammability: None,
+        hardness: 0.2,
       },
       Granular{
         typ: GranularType::Snow,
@@ -831,8 +2505,15 @@ impl World {
         granularity_90: 0.3,
         horizontal_spread: 0.05,
         spread_speed: 0.8,
-        fall_speed: 1.0,
+        fall_speed: 0.4,
         color: Color3::rgb(1.0, 1.0, 1.0),
+        density: 0.5,
+        erodibility: 0.2,
+        absorbency: 0.0,
+        sprouts: None,
+        acid_resistance: 0.2,
+        flammability: None,
+        hardness: 0.05,
       },
       Granular{
         typ: GranularType::Nitro,
@@ -843,6 +2524,98 @@ impl World {
         spread_speed: 0.8,
         fall_speed: 1.0,
         color: Color3::rgb(0.1, 0.4, 0.05),
+        density: 1.0,
+        erodibility: 0.3,
+        absorbency: 0.0,
+        sprouts: None,
+        acid_resistance: 0.3,
+        flammability: None,
+        hardness: 0.1,
+      },
+      Granular{
+        typ: GranularType::Stone,
+        name: "stone",
+        granularity_45: 0.6,
+        granularity_90: 0.1,
+        horizontal_spread: 0.02,
+        spread_speed: 0.5,
+        fall_speed: 1.5,
+        color: Color3::rgb(0.4, 0.38, 0.36),
+        density: 2.5,
+        erodibility: 0.02,
+        absorbency: 0.0,
+        sprouts: None,
+        acid_resistance: 0.8,
+        flammability: None,
+        hardness: 1.0,
+      },
+      Granular{
+        typ: GranularType::Ash,
+        name: "ash",
+        granularity_45: 0.5,
+        granularity_90: 0.1,
+        horizontal_spread: 0.1,
+        spread_speed: 0.8,
+        fall_speed: 0.6,
+        color: Color3::rgb(0.55, 0.55, 0.55),
+        density: 0.7,
+        erodibility: 0.8,
+        absorbency: 0.1,
+        sprouts: None,
+        acid_resistance: 0.3,
+        flammability: None,
+        hardness: 0.05,
+      },
+      Granular{
+        typ: GranularType::GrassSeed,
+        name: "grass seed",
+        granularity_45: 0.3,
+        granularity_90: 0.0,
+        horizontal_spread: 0.1,
+        spread_speed: 0.8,
+        fall_speed: 0.5,
+        color: Color3::rgb(0.75, 0.7, 0.35),
+        density: 0.8,
+        erodibility: 0.6,
+        absorbency: 0.0,
+        sprouts: Some(PlantType::Grass),
+        acid_resistance: 0.2,
+        flammability: Some(Flammability{ignition_chance: 0.4, burn_time: 5, smoke: 0.02, ash: 0.2}),
+        hardness: 0.05,
+      },
+      Granular{
+        typ: GranularType::ShrubSeed,
+        name: "shrub seed",
+        granularity_45: 0.3,
+        granularity_90: 0.0,
+        horizontal_spread: 0.1,
+        spread_speed: 0.8,
+        fall_speed: 0.8,
+        color: Color3::rgb(0.55, 0.4, 0.2),
+        density: 0.8,
+        erodibility: 0.6,
+        absorbency: 0.0,
+        sprouts: Some(PlantType::Shrub),
+        acid_resistance: 0.2,
+        flammability: Some(Flammability{ignition_chance: 0.4, burn_time: 5, smoke: 0.02, ash: 0.2}),
+        hardness: 0.05,
+      },
+      Granular{
+        typ: GranularType::TreeSeed,
+        name: "tree seed",
+        granularity_45: 0.3,
+        granularity_90: 0.0,
+        horizontal_spread: 0.1,
+        spread_speed: 0.8,
+        fall_speed: 1.0,
+        color: Color3::rgb(0.4, 0.25, 0.1),
+        density: 0.8,
+        erodibility: 0.6,
+        absorbency: 0.0,
+        sprouts: Some(PlantType::Tree),
+        acid_resistance: 0.2,
+        flammability: Some(Flammability{ignition_chance: 0.4, burn_time: 5, smoke: 0.02, ash: 0.2}),
+        hardness: 0.05,
       },
     ];
     let fluid = vec![
@@ -851,185 +2624,491 @@ impl World {
         name: "water",
         horizontal_spread: 0.05,
         fall_speed: 1.0,
+        viscosity: 0.0,
         compressibility: 0.05,
         color: Color3::rgb(0.0, 0.2, 1.0),
         density: 1.0,
-        down_dir: down_,
-        up_dir: up_,
+        gas: false,
+        transparency: 0.85,
+        acid_resistance: 1.0,
+        flammability: None,
+        hardness: 0.3,
       },
       Fluid{
         typ: FluidType::Oil,
         name: "oil",
         horizontal_spread: 0.05,
         fall_speed: 1.0,
+        viscosity: 0.2,
         compressibility: 0.05,
         color: Color3::rgb(0.5, 0.3, 0.0),
         density: 0.9,
-        down_dir: down_,
-        up_dir: up_,
+        gas: false,
+        transparency: 0.5,
+        acid_resistance: 0.9,
+        flammability: Some(Flammability{ignition_chance: 0.3, burn_time: 60, smoke: 0.1, ash: 0.0}),
+        hardness: 0.2,
       },
       Fluid{
         typ: FluidType::Methane,
         name: "methane",
         horizontal_spread: 0.05,
         fall_speed: 1.0,
+        viscosity: 0.0,
         compressibility: 0.05,
         color: Color3::rgb(0.15, 0.1, 0.1).blend(background_color(), 0.8),
         density: 0.5,
-        down_dir: up_,
-        up_dir: down_,
+        gas: true,
+        transparency: 1.0,
+        acid_resistance: 1.0,
+        flammability: Some(Flammability{ignition_chance: 1.0, burn_time: 3, smoke: 0.01, ash: 0.0}),
+        hardness: 0.05,
       },
       Fluid{
         typ: FluidType::Steam,
         name: "steam",
         horizontal_spread: 0.05,
         fall_speed: 1.0,
+        viscosity: 0.0,
         compressibility: 0.05,
         color: Color3::rgb(0.0, 0.2, 1.0).blend(Color3::white(), 0.6).blend(background_color(), 0.7),
         density: 0.3,
-        down_dir: up_,
-        up_dir: down_,
+        gas: true,
+        transparency: 0.85,
+        acid_resistance: 1.0,
+        flammability: None,
+        hardness: 0.05,
       },
       Fluid{
         typ: FluidType::Cement,
         name: "cement",
         horizontal_spread: 0.01,
-        fall_speed: 0.5, // TODO: this doesn't seem to do anything
+        fall_speed: 0.5,
+        viscosity: 0.8,
         compressibility: 0.01,
         color: Color3::rgb(0.3, 0.3, 0.3),
         density: 1.5,
-        down_dir: down_,
-        up_dir: up_,
+        gas: false,
+        transparency: 0.0,
+        acid_resistance: 0.6,
+        flammability: None,
+        hardness: 0.5,
+      },
+      Fluid{
+        typ: FluidType::Lava,
+        name: "lava",
+        horizontal_spread: 0.01,
+        fall_speed: 0.3,
+        viscosity: 0.9,
+        compressibility: 0.01,
+        color: Color3::rgb(1.0, 0.45, 0.05),
+        density: 3.0,
+        gas: false,
+        transparency: 0.0,
+        acid_resistance: 1.0,
+        flammability: None,
+        hardness: 1.0,
+      },
+      Fluid{
+        typ: FluidType::Acid,
+        name: "acid",
+        horizontal_spread: 0.05,
+        fall_speed: 1.0,
+        viscosity: 0.05,
+        compressibility: 0.05,
+        color: Color3::rgb(0.5, 1.0, 0.1),
+        density: 1.2,
+        gas: false,
+        transparency: 0.8,
+        acid_resistance: 1.0,
+        flammability: None,
+        hardness: 0.3,
+      },
+      Fluid{
+        typ: FluidType::Smoke,
+        name: "smoke",
+        horizontal_spread: 0.1,
+        fall_speed: 1.0,
+        viscosity: 0.0,
+        compressibility: 0.05,
+        color: Color3::rgb(0.2, 0.2, 0.2).blend(background_color(), 0.7),
+        density: 0.4,
+        gas: true,
+        transparency: 0.6,
+        acid_resistance: 1.0,
+        flammability: None,
+        hardness: 0.05,
+      },
+      Fluid{
+        typ: FluidType::Mud,
+        name: "mud",
+        horizontal_spread: 0.01,
+        fall_speed: 0.6,
+        viscosity: 0.7,
+        compressibility: 0.01,
+        color: Color3::rgb(0.35, 0.25, 0.15),
+        density: 1.4,
+        gas: false,
+        transparency: 0.0,
+        acid_resistance: 0.4,
+        flammability: None,
+        hardness: 0.3,
+      },
+      Fluid{
+        typ: FluidType::Cloud,
+        name: "cloud",
+        horizontal_spread: 0.1,
+        fall_speed: 1.0,
+        viscosity: 0.0,
+        compressibility: 0.05,
+        color: Color3::rgb(0.9, 0.9, 0.95),
+        density: 0.2,
+        gas: true,
+        transparency: 0.7,
+        acid_resistance: 1.0,
+        flammability: None,
+        hardness: 0.05,
+      },
+    ];
+    let plants = vec![
+      PlantSpecies{
+        typ: PlantType::Grass,
+        name: "grass",
+        height: 3,
+        root_depth: 2,
+        growth_rate: 0.05,
+        branch_chance: 0.0,
+        leaf_chance: 0.3,
+        seed_chance: 0.0005,
+        seed: GranularType::GrassSeed,
+        stem_color: Color3::rgb(0.3, 0.7, 0.2),
+        leaf_color: Color3::rgb(0.4, 0.8, 0.3),
+        flammability: Flammability{ignition_chance: 0.8, burn_time: 10, smoke: 0.02, ash: 0.1},
+      },
+      PlantSpecies{
+        typ: PlantType::Shrub,
+        name: "shrub",
+        height: 8,
+        root_depth: 5,
+        growth_rate: 0.03,
+        branch_chance: 0.25,
+        leaf_chance: 0.4,
+        seed_chance: 0.0003,
+        seed: GranularType::ShrubSeed,
+        stem_color: Color3::rgb(0.35, 0.5, 0.2),
+        leaf_color: Color3::rgb(0.2, 0.55, 0.15),
+        flammability: Flammability{ignition_chance: 0.5, burn_time: 30, smoke: 0.05, ash: 0.4},
+      },
+      PlantSpecies{
+        typ: PlantType::Tree,
+        name: "tree",
+        height: 25,
+        root_depth: 10,
+        growth_rate: 0.02,
+        branch_chance: 0.12,
+        leaf_chance: 0.2,
+        seed_chance: 0.0002,
+        seed: GranularType::TreeSeed,
+        stem_color: Color3::rgb(0.45, 0.3, 0.15),
+        leaf_color: Color3::rgb(0.1, 0.45, 0.1),
+        flammability: Flammability{ignition_chance: 0.3, burn_time: 80, smoke: 0.08, ash: 0.6},
+      },
+    ];
+    let life = vec![
+      // The original rule: other cells count as neighbours, with an extra birth/survival
+      // rule for 4 neighbours when they're mixed with life
+      Life{
+        typ: LifeType::Classic,
+        name: "life cell",
+        rule: LifeRule::parse("B356/S23").unwrap(),
+        count_nonlife: true,
+        mixed_rule: LifeRule::parse("B4/S4").unwrap(),
+        color: Color3::rgb(1.0, 1.0, 1.0),
+      },
+      Life{
+        typ: LifeType::Conway,
+        name: "conway life",
+        rule: LifeRule::parse("B3/S23").unwrap(),
+        count_nonlife: false,
+        mixed_rule: LifeRule::parse("B/S").unwrap(),
+        color: Color3::rgb(0.9, 0.9, 0.5),
+      },
+      Life{
+        typ: LifeType::HighLife,
+        name: "highlife",
+        rule: LifeRule::parse("B36/S23").unwrap(),
+        count_nonlife: false,
+        mixed_rule: LifeRule::parse("B/S").unwrap(),
+        color: Color3::rgb(0.5, 0.9, 0.9),
+      },
+      Life{
+        typ: LifeType::DayNight,
+        name: "day & night",
+        rule: LifeRule::parse("B3678/S34678").unwrap(),
+        count_nonlife: false,
+        mixed_rule: LifeRule::parse("B/S").unwrap(),
+        color: Color3::rgb(0.9, 0.5, 0.9),
+      },
+      Life{
+        typ: LifeType::Seeds,
+        name: "seeds",
+        rule: LifeRule::parse("B2/S").unwrap(),
+        count_nonlife: false,
+        mixed_rule: LifeRule::parse("B/S").unwrap(),
+        color: Color3::rgb(0.6, 1.0, 0.6),
       },
     ];
     let solid: HashMap<SolidType, Solid> = solid.into_iter().map(|x| (x.typ, x)).collect();
     let granular: HashMap<GranularType, Granular> = granular.into_iter().map(|x| (x.typ, x)).collect();
     let fluid: HashMap<FluidType, Fluid> = fluid.into_iter().map(|x| (x.typ, x)).collect();
+    let plants: HashMap<PlantType, PlantSpecies> = plants.into_iter().map(|x| (x.typ, x)).collect();
+    let life: HashMap<LifeType, Life> = life.into_iter().map(|x| (x.typ, x)).collect();
 
-    let grid = Grid{cells: cells, updated: updated, size: size, solid: solid, granular: granular, fluid: fluid};
-    World{grid: grid, mesh: mesh, texture: texture, coords: coords, pixels: pixels, id: Id::new(), unlit_program: unlit_program}
+    let heat = (0..size.y).map(|_| repeat(ambient_temp).take(size.x as usize).collect()).collect();
+    Grid{cells: cells, updated: updated, heat: heat, size: size, boundaries: Boundaries::all(Boundary::Solid), void_cell: Cell{typ: CellType::Empty},
+      solid: solid, granular: granular, fluid: fluid, plants: plants, life: life, particles: Vec::new(),
+      weather: Weather::new(), lighting: Lighting::new(size), gravity: Gravity::Uniform(down_), gravity_strength: 1.0}
   }
 
-  pub fn simulate<R: Rng>(&mut self, rng: &mut R) {
-    for y in 0..self.grid.size.y {
-      for x in 0..self.grid.size.x {
-        match self.grid.cells[y as usize][x as usize].typ {
-          CellType::Fluid(id, amount) if amount <= min_fluid => {
-            assert!(amount >= 0.0);
-            self.grid.cells[y as usize][x as usize].typ = CellType::Empty;
-          },
-          _ => ()
-        }
-
-        self.grid.updated[y as usize][x as usize] = false;
-      }
+  pub fn updated(&self, pos: Vec2<i32>) -> bool {
+    match self.locate(pos) {
+      Location::Inside(pos) => self.updated[pos.y as usize][pos.x as usize],
+      _ => true,
     }
+  }
+  pub fn update(&mut self, pos: Vec2<i32>) {
+    if let Location::Inside(pos) = self.locate(pos) {
+      self.updated[pos.y as usize][pos.x as usize] = true;
+    }
+  }
 
-    for &coord in self.coords.iter() {
-      self.grid.cells[coord.y as usize][coord.x as usize].simulate(&mut self.grid, coord, rng);
+  /// Whether a cell can be read and written at this position.
+  /// Positions past a wrapping or void edge are in range; the former refer to the cell at the
+  /// opposite edge and the latter are always empty.
+  pub fn in_range(&self, pos: Vec2<i32>) -> bool {
+    match self.locate(pos) {
+      Location::Outside => false,
+      _ => true,
     }
   }
 
-  pub fn update_mesh(&mut self, window: &Window) {
-    // TODO: with this here, why not just alloc pixels here?
-    self.pixels.clear();
+  /// The position inside the grid that a position refers to, after wrapping.
+  /// Returns None past void and solid edges.
+  pub fn resolve(&self, pos: Vec2<i32>) -> Option<Vec2<i32>> {
+    match self.locate(pos) {
+      Location::Inside(pos) => Some(pos),
+      _ => None,
+    }
+  }
 
-    let mut pixels = Vec::new();
-    for y in 0..self.grid.size.y as usize {
-      let mut row = Vec::new();
-      for x in 0..self.grid.size.x as usize {
-        let color = self.grid.cells[y][x].color(&self.grid);
-        row.push((color.r*255.0) as u8);
-        row.push((color.g*255.0) as u8);
-        row.push((color.b*255.0) as u8);
-      }
-      pixels.push(row);
+  fn locate(&self, pos: Vec2<i32>) -> Location {
+    if pos.x >= 0 && pos.y >= 0 && pos.x < self.size.x && pos.y < self.size.y {
+      return Location::Inside(pos);
     }
-    for y in 0..self.grid.size.y as usize {
-      let ref row = pixels[y];
-      for _ in 0..cell_size {
-        // let mut out_row = Vec::new();
-        for x in 0..self.grid.size.x as usize {
-          let r = row[x*3+0];
-          let g = row[x*3+1];
-          let b = row[x*3+2];
-          for _ in 0..cell_size {
-            /*self.pixels.push(r);
-            self.pixels.push(g);
-            self.pixels.push(b);*/
-            // out_row.push((r,g,b));
-            self.pixels.push((r,g,b));
-          }
-        }
-        // self.pixels.push(out_row);
+    let x_edge = if pos.x < 0 {Some(self.boundaries.left)}
+      else if pos.x >= self.size.x {Some(self.boundaries.right)}
+      else {None};
+    let y_edge = if pos.y < 0 {Some(self.boundaries.top)}
+      else if pos.y >= self.size.y {Some(self.boundaries.bottom)}
+      else {None};
+    let mut void = false;
+    for edge in [x_edge, y_edge].iter() {
+      match *edge {
+        None | Some(Boundary::Wrap) => (),
+        Some(Boundary::Void) => void = true,
+        Some(_) => return Location::Outside,
       }
     }
+    if void {
+      Location::Void
+    } else {
+      Location::Inside(Vec2(wrap(pos.x, self.size.x), wrap(pos.y, self.size.y)))
+    }
+  }
+
+  /// The direction things fall in at a position, rounded to one of the eight neighbours.
+  /// Returns None where nothing falls.
+  pub fn down_at(&self, pos: Vec2<i32>) -> Option<Vec2<i32>> {
+    match self.gravity {
+      Gravity::Uniform(down) => Some(down),
+      Gravity::Point(center) if center != pos => {
+        let offset = center - pos;
+        let angle = (offset.y as f64).atan2(offset.x as f64);
+        Some(directions[wrap((angle / (PI/4.0)).round() as i32, 8) as usize])
+      },
+      _ => None,
+    }
+  }
 
-    // TODO: can I avoid the clone?
-    let raw = RawImage2d{
-      data: Cow::Borrowed(&self.pixels),
-      width: (self.grid.size.x*cell_size) as u32,
-      height: (self.grid.size.y*cell_size) as u32,
-      format: ClientFormat::U8U8U8
+  /// The unrounded pull of gravity at a position, scaled by its strength
+  pub fn pull_at(&self, pos: Vec2<f64>) -> Vec2<f64> {
+    let dir = match self.gravity {
+      Gravity::Uniform(down) => Vec2(down.x as f64, down.y as f64),
+      Gravity::Point(center) => Vec2(center.x as f64, center.y as f64) - pos,
+      Gravity::Zero => return Vec2::zero(),
     };
-    self.texture.write(glium::Rect{left: 0, width: (self.grid.size.x*cell_size) as u32,
-      bottom: 0, height: (self.grid.size.y*cell_size) as u32}, raw);
-    // self.texture = texture::Texture2d::with_mipmaps(window, raw, false);
+    let length = dir.norm();
+    if length == 0.0 {
+      return Vec2::zero();
+    }
+    dir * (self.gravity_strength / length)
+  }
 
-    // self.texture.update_texture2d_from_pixels(&self.pixels);
+  /// How high up a position is, measured against gravity
+  pub fn height(&self, pos: Vec2<i32>) -> f64 {
+    match self.gravity {
+      Gravity::Uniform(down) => -(pos.x*down.x + pos.y*down.y) as f64 / ((down.x*down.x + down.y*down.y) as f64).sqrt(),
+      Gravity::Point(center) => {
+        let offset = pos - center;
+        ((offset.x*offset.x + offset.y*offset.y) as f64).sqrt()
+      },
+      Gravity::Zero => 0.0,
+    }
   }
-}
 
-impl Widget for World {
-  fn id(&self) -> &Id {&self.id}
-  fn draw(&mut self, pos: Vec2<i32>, size: Vec2<i32>, facade: &DrawContext, frame: &mut DrawFrame) {//window: &mut Window) {
+  /// The height of the highest point in the world
+  pub fn top_height(&self) -> f64 {
+    let corners = [Vec2(0, 0), Vec2(self.size.x-1, 0), Vec2(0, self.size.y-1), self.size - Vec2(1, 1)];
+    corners.iter().map(|&corner| self.height(corner)).fold(::std::f64::MIN, f64::max)
+  }
 
-    let indices = index::NoIndices(index::PrimitiveType::TriangleFan);
-    let uniforms = uniform!{
-      modelViewMatrix: Mat4::generic_ortho(
-      Vec2::zero(), Vec2(self.grid.size.x as f32, self.grid.size.y as f32),
-      Vec2::<f32>::gen_from(pos), Vec2::<f32>::gen_from(pos+size)),
-      projMatrix: Mat4::ortho_flip(frame.width() as f32, frame.height() as f32), // TODO //window.window_size.x as f32, window.window_size.y as f32),
-      tex: Sampler::new(&self.texture), // TODO: filters
-    };
-    // println!("Drawing world; {} {}", frame.width(), frame.height());
-    frame.draw(&self.mesh, &indices, &self.unlit_program, &uniforms, &default_draw_params/*, None*/);
+  /// Finds the Life species that uses the given rule
+  pub fn life_type_for_rule(&self, rule: LifeRule) -> Option<LifeType> {
+    self.life.values().find(|life| life.rule == rule && !life.count_nonlife).map(|life| life.typ)
+  }
 
-    /*self.mesh.draw(UnlitUniforms{
-      model_view_matrix: Mat4::generic_ortho(
-      Vec2::zero(), Vec2(self.grid.size.x as f32, self.grid.size.y as f32),
-      Vec2::<f32>::gen_from(pos), Vec2::<f32>::gen_from(pos+size)),
-      proj_matrix: Mat4::ortho_flip(window.window_size.x as f32, window.window_size.y as f32),
-      tex: &self.texture,
-    });*/
+  /// Fills empty cells along inflow edges
+  pub fn inflow<R: Rng>(&mut self, rng: &mut R) {
+    let size = self.size;
+    let edges = [
+      (self.boundaries.top, Vec2(0, 0), Vec2(1, 0), size.x),
+      (self.boundaries.bottom, Vec2(0, size.y-1), Vec2(1, 0), size.x),
+      (self.boundaries.left, Vec2(0, 0), Vec2(0, 1), size.y),
+      (self.boundaries.right, Vec2(size.x-1, 0), Vec2(0, 1), size.y),
+    ];
+    for &(boundary, start, step, len) in edges.iter() {
+      if let Boundary::Inflow(typ, rate) = boundary {
+        for i in 0..len {
+          let pos = start + step*i;
+          if self[pos].typ == CellType::Empty && rng.gen::<f64>() < rate {
+            self[pos].typ = typ;
+          }
+        }
+      }
+    }
   }
 
-  fn min_size(&self, facade: &DrawContext) -> Vec2<i32> {
-    self.grid.size * cell_size
+  pub fn heat(&self, pos: Vec2<i32>) -> f64 {
+    match self.locate(pos) {
+      Location::Inside(pos) => self.heat[pos.y as usize][pos.x as usize],
+      _ => ambient_temp,
+    }
   }
-}
 
+  /// Spreads heat from heat sources through conductors. Conductors slowly lose heat to their
+  /// other neighbours, and everything else stays at the ambient temperature.
+  pub fn conduct_heat(&mut self) {
+    let mut new_heat = self.heat.clone();
+    for y in 0..self.size.y {
+      for x in 0..self.size.x {
+        let pos = Vec2(x, y);
+        let typ = self[pos].typ;
+        let conductivity = typ.heat_conductivity(self);
+        new_heat[y as usize][x as usize] = if let Some(temp) = typ.heat_source() {
+          temp
+        } else if conductivity > 0.0 {
+          let heat = self.heat[y as usize][x as usize];
+          let mut new = heat;
+          for &dir in [up_, down_, left_, right_].iter() {
+            let neighbor = pos + dir;
+            if !self.in_range(neighbor) {
+              continue;
+            }
+            let neighbor_typ = self[neighbor].typ;
+            let rate = if neighbor_typ.heat_source().is_some() {conductivity}
+              else {neighbor_typ.heat_conductivity(self).min(conductivity).max(heat_loss)};
+            new += rate * (self.heat(neighbor) - heat);
+          }
+          new
+        } else {
+          ambient_temp
+        };
+      }
+    }
+    self.heat = new_heat;
+  }
 
+  /// Moves liquid from the free surface of each connected body of liquid to the lowest empty
+  /// cell next to the body, if that's more than a cell lower. This models hydrostatic pressure:
+  /// levels even out across connected vessels, liquid pushes up through gaps, and full tubes act
+  /// as siphons. Only surfaces open to the air above and hemmed in at the sides count, so falling
+  /// streams and the tops of siphon tubes don't get moved. Openings right under the body don't
+  /// count either, so liquid still falls at its own speed (see Cell::simulate), and viscous
+  /// liquids level out more slowly.
+  pub fn equalize_pressure<R: Rng>(&mut self, rng: &mut R) {
+    let mut visited = vec![vec![false; self.size.x as usize]; self.size.y as usize];
+    for y in 0..self.size.y {
+      for x in 0..self.size.x {
+        let id = match self.cells[y as usize][x as usize].typ {
+          CellType::Fluid(id, _) if !visited[y as usize][x as usize] && !self.fluid[&id].gas => id,
+          _ => continue,
+        };
 
-pub struct Grid {
-  pub size: Vec2<i32>,
-  cells: Vec<Vec<Cell>>,
-  updated: Vec<Vec<bool>>,
-  solid: HashMap<SolidType, Solid>,
-  granular: HashMap<GranularType, Granular>,
-  fluid: HashMap<FluidType, Fluid>,
-}
+        // Flood fill the body, collecting its free surface and the empty cells around it
+        let mut stack = vec![Vec2(x, y)];
+        visited[y as usize][x as usize] = true;
+        let mut surface = Vec::new();
+        let mut openings = HashSet::new();
+        let mut below = HashSet::new();
+        while let Some(pos) = stack.pop() {
+          let is_empty = |grid: &Grid, dir: Vec2<i32>| match grid.resolve(pos+dir) {
+            Some(other) => grid[other].typ == CellType::Empty,
+            None => false,
+          };
+          if let Some(down) = self.down_at(pos) {
+            let (up, _, left, right, _, _) = relative_directions(down);
+            if is_empty(self, up) && !is_empty(self, left) && !is_empty(self, right) {
+              surface.push(pos);
+            }
+            if let Some(under) = self.resolve(pos+down) {
+              below.insert(under);
+            }
+          }
+          for &dir in [up_, down_, left_, right_].iter() {
+            if let Some(other) = self.resolve(pos+dir) {
+              match self[other].typ {
+                CellType::Fluid(id2, _) if id2 == id && !visited[other.y as usize][other.x as usize] => {
+                  visited[other.y as usize][other.x as usize] = true;
+                  stack.push(other);
+                },
+                CellType::Empty => {openings.insert(other);},
+                _ => (),
+              }
+            }
+          }
+        }
 
-impl Grid {
-  pub fn updated(&self, pos: Vec2<i32>) -> bool {
-    self.updated[pos.y as usize][pos.x as usize]
-  }
-  pub fn update(&mut self, pos: Vec2<i32>) {
-    self.updated[pos.y as usize][pos.x as usize] = true;
+        // Pair the highest surface cells with the lowest openings
+        rng.shuffle(&mut surface);
+        surface.sort_by(|&a, &b| self.height(b).partial_cmp(&self.height(a)).unwrap());
+        let mut openings = openings.difference(&below).cloned().collect::<Vec<_>>();
+        rng.shuffle(&mut openings);
+        openings.sort_by(|&a, &b| self.height(a).partial_cmp(&self.height(b)).unwrap());
+        let moves = cells_this_tick(pressure_moves as f64 * (1.0 - self.fluid[&id].viscosity), rng);
+        for (&from, &to) in surface.iter().zip(openings.iter()).take(moves as usize) {
+          if self.height(from) - self.height(to) <= 1.0 {
+            break;
+          }
+          self[to].typ = self[from].typ;
+          self[from].typ = CellType::Empty;
+        }
+      }
+    }
   }
 
-  pub fn in_range(&self, pos: Vec2<i32>) -> bool {
-    pos.x >= 0 && pos.y >= 0 && pos.x < self.size.x && pos.y < self.size.y
+  /// Whether an adjacent output wire is carrying an electron head
+  pub fn powered(&self, pos: Vec2<i32>) -> bool {
+    self.moore(pos).iter().any(|cell| cell.typ == CellType::ElectronHead(WireType::Output))
   }
 
   pub fn moore(&self, pos: Vec2<i32>) -> Vec<Cell> {
@@ -1045,15 +3124,180 @@ impl Grid {
   }
 }
 
+fn wrap(x: i32, size: i32) -> i32 {
+  ((x % size) + size) % size
+}
+
 impl Index<Vec2<i32>> for Grid {
   type Output = Cell;
   fn index(&self, index: Vec2<i32>) -> &Cell {
-    &self.cells[index.y as usize][index.x as usize]
+    match self.locate(index) {
+      Location::Inside(pos) => &self.cells[pos.y as usize][pos.x as usize],
+      Location::Void => &empty_cell,
+      Location::Outside => panic!("Cell out of range: ({}, {})", index.x, index.y),
+    }
   }
 }
 
 impl IndexMut<Vec2<i32>> for Grid {
   fn index_mut(&mut self, index: Vec2<i32>) -> &mut Cell {
-    &mut self.cells[index.y as usize][index.x as usize]
+    match self.locate(index) {
+      Location::Inside(pos) => &mut self.cells[pos.y as usize][pos.x as usize],
+      Location::Void => &mut self.void_cell,
+      Location::Outside => panic!("Cell out of range: ({}, {})", index.x, index.y),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand;
+
+  const center: Vec2<i32> = Vec2{x: 2, y: 2};
+
+  /// A 5x5 grid with the given cells, placed relative to its centre
+  fn grid_with(cells: &[(Vec2<i32>, CellType)]) -> Grid {
+    let mut grid = Grid::new(Vec2(5, 5));
+    for &(offset, typ) in cells {
+      grid[center + offset].typ = typ;
+    }
+    grid
+  }
+
+  /// Runs one tick of a single cell, and returns what it became
+  fn step(grid: &mut Grid, pos: Vec2<i32>) -> CellType {
+    let cell = grid[pos];
+    cell.simulate(grid, pos, &mut rand::thread_rng());
+    grid[pos].typ
+  }
+
+  /// Whether a gate fires with the given number of adjacent input heads
+  fn gate_fires(gate: WireType, heads: usize) -> bool {
+    let inputs = [Vec2(-1, 0), Vec2(1, 0), Vec2(0, -1)];
+    let mut cells = vec![(Vec2(0, 0), CellType::Wire(gate))];
+    for (i, &offset) in inputs.iter().enumerate() {
+      let input = if i < heads {CellType::ElectronHead(WireType::Input)} else {CellType::Wire(WireType::Input)};
+      cells.push((offset, input));
+    }
+    let mut grid = grid_with(&cells);
+    step(&mut grid, center) == CellType::ElectronHead(gate)
+  }
+
+  #[test]
+  fn and_gate() {
+    assert_eq!((0..4).map(|heads| gate_fires(WireType::And, heads)).collect::<Vec<_>>(), vec![false, false, true, true]);
+  }
+
+  #[test]
+  fn or_gate() {
+    assert_eq!((0..4).map(|heads| gate_fires(WireType::Or, heads)).collect::<Vec<_>>(), vec![false, true, true, true]);
+  }
+
+  #[test]
+  fn xor_gate() {
+    assert_eq!((0..4).map(|heads| gate_fires(WireType::Xor, heads)).collect::<Vec<_>>(), vec![false, true, false, false]);
+  }
+
+  #[test]
+  fn gates_only_listen_to_inputs() {
+    for &gate in [WireType::And, WireType::Or, WireType::Xor].iter() {
+      let mut grid = grid_with(&[(Vec2(0, 0), CellType::Wire(gate)),
+        (Vec2(-1, 0), CellType::ElectronHead(WireType::Normal)), (Vec2(1, 0), CellType::ElectronHead(WireType::Normal))]);
+      assert!(step(&mut grid, center) == CellType::Wire(gate));
+    }
+  }
+
+  #[test]
+  fn gates_only_signal_to_outputs() {
+    for &gate in [WireType::And, WireType::Or, WireType::Xor].iter() {
+      assert!(gate.can_signal_to(WireType::Output));
+      assert!(!gate.can_signal_to(WireType::Normal));
+      assert!(!gate.can_signal_to(WireType::Input));
+    }
+  }
+
+  #[test]
+  fn diode_direction() {
+    assert!(WireType::Normal.can_signal_to(WireType::Diode));
+    assert!(WireType::Input.can_signal_to(WireType::Diode));
+    assert!(WireType::Diode.can_signal_to(WireType::Output));
+    assert!(!WireType::Output.can_signal_to(WireType::Diode));
+    assert!(!WireType::Diode.can_signal_to(WireType::Normal));
+    assert!(!WireType::Diode.can_signal_to(WireType::Input));
+
+    // A head on the output side doesn't get through backwards
+    let mut grid = grid_with(&[(Vec2(0, 0), CellType::Wire(WireType::Diode)), (Vec2(1, 0), CellType::ElectronHead(WireType::Output))]);
+    assert!(step(&mut grid, center) == CellType::Wire(WireType::Diode));
+    let mut grid = grid_with(&[(Vec2(0, 0), CellType::Wire(WireType::Diode)), (Vec2(-1, 0), CellType::ElectronHead(WireType::Normal))]);
+    assert!(step(&mut grid, center) == CellType::ElectronHead(WireType::Diode));
+  }
+
+  /// The ticks (counting from 1) on which a cell is an electron head
+  fn firing_ticks(grid: &mut Grid, ticks: i32) -> Vec<i32> {
+    (1..ticks+1).filter(|_| match step(grid, center) {CellType::ElectronHead(_) => true, _ => false}).collect()
+  }
+
+  #[test]
+  fn clock_period() {
+    for &period in [4, 5, 9].iter() {
+      let mut grid = grid_with(&[(Vec2(0, 0), CellType::Wire(WireType::Clock(period, 0)))]);
+      assert_eq!(firing_ticks(&mut grid, period*3), vec![1, 1+period, 1+period*2]);
+    }
+  }
+
+  #[test]
+  fn clock_ignores_signals() {
+    let mut grid = grid_with(&[(Vec2(0, 0), CellType::Wire(WireType::Clock(6, 3))), (Vec2(-1, 0), CellType::ElectronHead(WireType::Normal))]);
+    assert!(step(&mut grid, center) == CellType::Wire(WireType::Clock(6, 2)));
+  }
+
+  #[test]
+  fn delay_fires_late() {
+    for delay in 0..5 {
+      // A single pulse arrives on the first tick; a normal wire would fire then
+      let mut grid = grid_with(&[(Vec2(0, 0), CellType::Wire(WireType::Delay(delay, 0))), (Vec2(-1, 0), CellType::ElectronHead(WireType::Normal))]);
+      let first = step(&mut grid, center);
+      grid[center + Vec2(-1, 0)].typ = CellType::Wire(WireType::Normal);
+      let mut fired = match first {CellType::ElectronHead(_) => vec![1], _ => vec![]};
+      fired.extend(firing_ticks(&mut grid, 10).into_iter().map(|tick| tick+1));
+      assert_eq!(fired, vec![1+delay]);
+    }
+  }
+
+  #[test]
+  fn material_sensor_ignores_state() {
+    let sand = SensorType::Material(Material::Granular(GranularType::Sand));
+    assert!(sand.is_triggered_by(CellType::Granular(GranularType::Sand, true, false, 0.5)));
+    assert!(!sand.is_triggered_by(CellType::Granular(GranularType::Dirt, false, false, 0.0)));
+    let door = SensorType::Material(CellType::Door(0).material());
+    assert!(door.is_triggered_by(CellType::Door(power_hold_time)));
+    assert!(!door.is_triggered_by(CellType::Valve(0)));
+  }
+
+  #[test]
+  fn delay_ignores_signals_while_waiting() {
+    let mut grid = grid_with(&[(Vec2(0, 0), CellType::Wire(WireType::Delay(3, 3))), (Vec2(-1, 0), CellType::ElectronHead(WireType::Normal))]);
+    assert!(step(&mut grid, center) == CellType::Wire(WireType::Delay(3, 2)));
+  }
+
+  #[test]
+  fn powered_torch_only_shines_while_powered() {
+    let torch = CellType::Torch(true);
+    let grid = grid_with(&[(Vec2(0, 0), torch), (Vec2(-1, 0), CellType::Wire(WireType::Output))]);
+    assert_eq!(torch.light_source(&grid, center), None);
+    let grid = grid_with(&[(Vec2(0, 0), torch), (Vec2(-1, 0), CellType::ElectronHead(WireType::Output))]);
+    assert_eq!(torch.light_source(&grid, center), Some(1.0));
+    assert_eq!(CellType::Torch(false).light_source(&grid_with(&[]), center), Some(1.0));
+  }
+
+  #[test]
+  fn powered_metal_is_still_metal() {
+    let grid = grid_with(&[]);
+    let metal = CellType::Solid(SolidType::Metal);
+    for &typ in [CellType::ElectronHead(WireType::Metal), CellType::ElectronTail(WireType::Metal, 1)].iter() {
+      assert_eq!(typ.hardness(&grid), metal.hardness(&grid));
+      assert_eq!(typ.acid_resistance(&grid), metal.acid_resistance(&grid));
+    }
   }
 }
@@ -2,8 +2,30 @@
 
 in vec2 Texcoord;
 
+// Red holds the material ID, green holds the shade (or the heatmap value in overlay mode),
+// blue holds how brightly lit the cell is, and alpha darkens the material's colour
 uniform sampler2D tex;
+uniform sampler1D palette;
+uniform vec3 background;
+uniform int overlay;
+
+vec3 heat(float t) {
+  return clamp(vec3(1.5 - abs(4.0*t - 3.0), 1.5 - abs(4.0*t - 2.0), 1.5 - abs(4.0*t - 1.0)), 0.0, 1.0);
+}
 
 void main() {
-  gl_FragColor = texture2D(tex, Texcoord);
+  vec4 cell = texture2D(tex, Texcoord);
+  float id = floor(cell.r * 255.0 + 0.5);
+  vec3 color = texture1D(palette, (id + 0.5) / 256.0).rgb;
+  if (overlay == 0) {
+    gl_FragColor = vec4(mix(background, color * cell.a, cell.g) * cell.b, 1.0);
+  } else {
+    float value = floor(cell.g * 255.0 + 0.5);
+    if (value > 0.0) {
+      gl_FragColor = vec4(heat((value - 1.0) / 254.0), 1.0);
+    } else {
+      // Dim everything that isn't highlighted so the heatmap stands out
+      gl_FragColor = vec4(vec3(dot(color, vec3(0.3, 0.59, 0.11)) * 0.4), 1.0);
+    }
+  }
 }